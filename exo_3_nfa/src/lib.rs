@@ -1,34 +1,707 @@
-use std::collections::HashSet;
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::hash::Hash;
+
+/// Upper bound on the number of paths returned by [`Nfa::all_accepting_paths`].
+///
+/// The number of accepting runs can grow exponentially with the input length,
+/// so enumeration stops once this many paths have been found.
+pub const MAX_ACCEPTING_PATHS: usize = 1024;
 
 pub struct Nfa {
-    // TODO: add members
+    transitions: Vec<HashMap<char, HashSet<State>>>,
+    epsilon: Vec<HashSet<State>>,
+    initial: HashSet<State>,
+    finals: HashSet<State>,
 }
 
 type State = usize;
 
+/// Errors reported by the checked `try_*` builder methods of [`Nfa`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NfaError {
+    /// The state is not smaller than the number of states of the automaton.
+    NoSuchState(State),
+}
+
+impl fmt::Display for NfaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NfaError::NoSuchState(q) => write!(f, "no such state: {q}"),
+        }
+    }
+}
+
+impl std::error::Error for NfaError {}
+
 impl Nfa {
     pub fn new(n_states: usize) -> Self {
-        todo!("Create a new Nfa with n_states states")
+        Self {
+            transitions: vec![HashMap::new(); n_states],
+            epsilon: vec![HashSet::new(); n_states],
+            initial: HashSet::new(),
+            finals: HashSet::new(),
+        }
     }
 
+    pub fn n_states(&self) -> usize {
+        self.transitions.len()
+    }
+
+    /// # Panics
+    ///
+    /// Panics if `from` or `to` is not a state of `self`,
+    /// see [`Nfa::try_add_transition`] for a checked version.
     pub fn add_transition(&mut self, from: State, to: State, label: char) {
-        todo!("Add a transition to Self")
+        self.try_add_transition(from, to, label).unwrap();
+    }
+
+    /// Add a transition from `from` to `to` reading no symbol.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `from` or `to` is not a state of `self`,
+    /// see [`Nfa::try_add_epsilon_transition`] for a checked version.
+    pub fn add_epsilon_transition(&mut self, from: State, to: State) {
+        self.try_add_epsilon_transition(from, to).unwrap();
     }
 
+    /// # Panics
+    ///
+    /// Panics if `q` is not a state of `self`,
+    /// see [`Nfa::try_add_initial`] for a checked version.
     pub fn add_initial(&mut self, q: State) {
-        todo!("Add q to the set of initial states");
+        self.try_add_initial(q).unwrap();
     }
 
+    /// # Panics
+    ///
+    /// Panics if `q` is not a state of `self`,
+    /// see [`Nfa::try_add_final`] for a checked version.
     pub fn add_final(&mut self, q: State) {
-        todo!("Add q to the set of final states");
+        self.try_add_final(q).unwrap();
+    }
+
+    pub fn try_add_transition(
+        &mut self,
+        from: State,
+        to: State,
+        label: char,
+    ) -> Result<(), NfaError> {
+        self.check_state(from)?;
+        self.check_state(to)?;
+        self.transitions[from].entry(label).or_default().insert(to);
+        Ok(())
+    }
+
+    pub fn try_add_epsilon_transition(&mut self, from: State, to: State) -> Result<(), NfaError> {
+        self.check_state(from)?;
+        self.check_state(to)?;
+        self.epsilon[from].insert(to);
+        Ok(())
+    }
+
+    pub fn try_add_initial(&mut self, q: State) -> Result<(), NfaError> {
+        self.check_state(q)?;
+        self.initial.insert(q);
+        Ok(())
+    }
+
+    pub fn try_add_final(&mut self, q: State) -> Result<(), NfaError> {
+        self.check_state(q)?;
+        self.finals.insert(q);
+        Ok(())
+    }
+
+    /// Add a transition reading the byte `label`, see [`Nfa::accepts_bytes`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if `from` or `to` is not a state of `self`.
+    pub fn add_byte_transition(&mut self, from: State, to: State, label: u8) {
+        self.add_transition(from, to, char::from(label));
     }
 
+    fn check_state(&self, q: State) -> Result<(), NfaError> {
+        if q < self.n_states() {
+            Ok(())
+        } else {
+            Err(NfaError::NoSuchState(q))
+        }
+    }
+
+    /// Add to `states` every state reachable from them through ε-transitions.
+    fn closure(&self, mut states: HashSet<State>) -> HashSet<State> {
+        let mut stack: Vec<State> = states.iter().copied().collect();
+        while let Some(q) = stack.pop() {
+            for &r in &self.epsilon[q] {
+                if states.insert(r) {
+                    stack.push(r);
+                }
+            }
+        }
+        states
+    }
+
+    /// Read `a` from `states`, then follow ε-transitions.
     fn step(&self, states: HashSet<State>, a: char) -> HashSet<State> {
-        todo!("Compute the transition labeled by a in Self")
+        let next = states
+            .into_iter()
+            .filter_map(|q| self.transitions[q].get(&a))
+            .flatten()
+            .copied()
+            .collect();
+        self.closure(next)
+    }
+
+    /// Same as [`Nfa::step`], on a sorted set of states.
+    fn step_sorted(&self, states: &BTreeSet<State>, a: char) -> BTreeSet<State> {
+        self.step(states.iter().copied().collect(), a)
+            .into_iter()
+            .collect()
+    }
+
+    /// The states reached after reading `input` from `states`, ε-transitions included.
+    fn run<I: IntoIterator<Item = char>>(
+        &self,
+        states: HashSet<State>,
+        input: I,
+    ) -> HashSet<State> {
+        input
+            .into_iter()
+            .fold(self.closure(states), |states, a| self.step(states, a))
+    }
+
+    fn alphabet(&self) -> BTreeSet<char> {
+        self.transitions
+            .iter()
+            .flat_map(|t| t.keys())
+            .copied()
+            .collect()
     }
 
     pub fn accepts(&self, s: &str) -> bool {
-        todo!("Test whether s is in the language of Self")
+        self.accepts_iter(s.chars())
+    }
+
+    /// Test whether the sequence of symbols `input` is in the language of `self`.
+    pub fn accepts_iter<I: IntoIterator<Item = char>>(&self, input: I) -> bool {
+        let states = self.run(self.initial.clone(), input);
+        states.iter().any(|q| self.finals.contains(q))
+    }
+
+    /// Test whether the byte string `input` is in the language of `self`.
+    ///
+    /// Each byte `b` is read as the character `char::from(b)` (U+0000 to U+00FF),
+    /// so byte transitions should be added with [`Nfa::add_byte_transition`].
+    pub fn accepts_bytes(&self, input: &[u8]) -> bool {
+        self.accepts_iter(input.iter().map(|&b| char::from(b)))
+    }
+
+    /// Split `input` into tokens by maximal munch: from the current position,
+    /// the longest nonempty prefix accepted by `self` is emitted as a token
+    /// and scanning resumes after it.
+    ///
+    /// When no nonempty prefix is accepted, one character is skipped.
+    pub fn scan<'a>(&self, input: &'a str) -> Vec<&'a str> {
+        let mut tokens = Vec::new();
+        let mut rest = input;
+        while let Some(first) = rest.chars().next() {
+            let mut states = self.closure(self.initial.clone());
+            let mut longest = None;
+            for (i, a) in rest.char_indices() {
+                states = self.step(states, a);
+                if states.is_empty() {
+                    break;
+                }
+                if states.iter().any(|q| self.finals.contains(q)) {
+                    longest = Some(i + a.len_utf8());
+                }
+            }
+            let end = match longest {
+                Some(end) => {
+                    tokens.push(&rest[..end]);
+                    end
+                }
+                None => first.len_utf8(),
+            };
+            rest = &rest[end..];
+        }
+        tokens
+    }
+
+    /// Build an automaton accepting the words `w` such that `w` followed by
+    /// `suffix` is accepted by `self`.
+    ///
+    /// The transitions and initial states are kept, and a state becomes final
+    /// when reading `suffix` from it can reach a final state of `self`.
+    pub fn right_quotient(&self, suffix: &str) -> Nfa {
+        let finals = (0..self.transitions.len())
+            .filter(|&q| {
+                self.run(HashSet::from([q]), suffix.chars())
+                    .iter()
+                    .any(|r| self.finals.contains(r))
+            })
+            .collect();
+        Nfa {
+            transitions: self.transitions.clone(),
+            epsilon: self.epsilon.clone(),
+            initial: self.initial.clone(),
+            finals,
+        }
+    }
+
+    /// Enumerate the distinct state sequences along which `s` is accepted.
+    ///
+    /// Each path has one more state than `s` has characters, starting in an
+    /// initial state and ending in a final one. Paths are returned in
+    /// lexicographic order, and at most [`MAX_ACCEPTING_PATHS`] are returned.
+    ///
+    /// ε-transitions are not followed, see [`Nfa::remove_epsilon`].
+    pub fn all_accepting_paths(&self, s: &str) -> Vec<Vec<State>> {
+        let word: Vec<char> = s.chars().collect();
+        let mut initial: Vec<State> = self.initial.iter().copied().collect();
+        initial.sort_unstable();
+
+        let mut paths = Vec::new();
+        let mut path = Vec::with_capacity(word.len() + 1);
+        for q in initial {
+            path.push(q);
+            self.collect_paths(&word, &mut path, &mut paths);
+            path.pop();
+        }
+        paths
+    }
+
+    fn collect_paths(&self, word: &[char], path: &mut Vec<State>, paths: &mut Vec<Vec<State>>) {
+        if paths.len() >= MAX_ACCEPTING_PATHS {
+            return;
+        }
+        let q = *path.last().unwrap();
+        let Some((&a, rest)) = word.split_first() else {
+            if self.finals.contains(&q) {
+                paths.push(path.clone());
+            }
+            return;
+        };
+
+        let Some(succ) = self.transitions[q].get(&a) else {
+            return;
+        };
+        let mut succ: Vec<State> = succ.iter().copied().collect();
+        succ.sort_unstable();
+        for r in succ {
+            path.push(r);
+            self.collect_paths(rest, path, paths);
+            path.pop();
+        }
+    }
+
+    /// The adjacency matrix of the transitions labeled by `symbol`:
+    /// entry `[i][j]` is true iff there is a transition from `i` to `j` reading `symbol`.
+    ///
+    /// ε-transitions are not included.
+    pub fn transition_matrix(&self, symbol: char) -> Vec<Vec<bool>> {
+        self.transitions
+            .iter()
+            .map(|t| {
+                let mut row = vec![false; self.n_states()];
+                for &q in t.get(&symbol).into_iter().flatten() {
+                    row[q] = true;
+                }
+                row
+            })
+            .collect()
+    }
+
+    /// Build an automaton accepting the union of the languages of `self` and `other`.
+    ///
+    /// The result is the disjoint union of both automata, the states of `other`
+    /// being renumbered after those of `self`. Since several initial states are
+    /// allowed, no ε-transition is added.
+    pub fn union_no_epsilon(&self, other: &Nfa) -> Nfa {
+        let offset = self.n_states();
+        let shift = |states: &HashSet<State>| -> HashSet<State> {
+            states.iter().map(|q| q + offset).collect()
+        };
+
+        let mut nfa = Nfa {
+            transitions: self.transitions.clone(),
+            epsilon: self.epsilon.clone(),
+            initial: self.initial.clone(),
+            finals: self.finals.clone(),
+        };
+        nfa.transitions.extend(other.transitions.iter().map(|t| {
+            t.iter()
+                .map(|(&a, targets)| (a, shift(targets)))
+                .collect::<HashMap<_, _>>()
+        }));
+        nfa.epsilon.extend(other.epsilon.iter().map(shift));
+        nfa.initial.extend(shift(&other.initial));
+        nfa.finals.extend(shift(&other.finals));
+        nfa
+    }
+
+    /// The states reachable from an initial state.
+    pub fn reachable_states(&self) -> HashSet<State> {
+        let mut reached = self.initial.clone();
+        let mut stack: Vec<State> = reached.iter().copied().collect();
+        while let Some(q) = stack.pop() {
+            let successors = self.transitions[q]
+                .values()
+                .flatten()
+                .chain(&self.epsilon[q]);
+            for &r in successors {
+                if reached.insert(r) {
+                    stack.push(r);
+                }
+            }
+        }
+        reached
+    }
+
+    /// The states from which a final state is reachable.
+    pub fn co_reachable_states(&self) -> HashSet<State> {
+        let mut predecessors = vec![Vec::new(); self.n_states()];
+        for q in 0..self.n_states() {
+            for &r in self.transitions[q]
+                .values()
+                .flatten()
+                .chain(&self.epsilon[q])
+            {
+                predecessors[r].push(q);
+            }
+        }
+
+        let mut reached = self.finals.clone();
+        let mut stack: Vec<State> = reached.iter().copied().collect();
+        while let Some(r) = stack.pop() {
+            for &q in &predecessors[r] {
+                if reached.insert(q) {
+                    stack.push(q);
+                }
+            }
+        }
+        reached
+    }
+
+    /// The states that are reachable but from which no final state is reachable:
+    /// a run entering them can never accept.
+    pub fn dead_states(&self) -> HashSet<State> {
+        let co_reachable = self.co_reachable_states();
+        self.reachable_states()
+            .into_iter()
+            .filter(|q| !co_reachable.contains(q))
+            .collect()
+    }
+
+    /// Build an automaton accepting one or more repetitions of words of `self`.
+    ///
+    /// ε-transitions are added from every final state to every initial state,
+    /// so the empty word is only accepted if `self` accepts it.
+    pub fn plus(&self) -> Nfa {
+        let mut nfa = Nfa {
+            transitions: self.transitions.clone(),
+            epsilon: self.epsilon.clone(),
+            initial: self.initial.clone(),
+            finals: self.finals.clone(),
+        };
+        for &q in &self.finals {
+            nfa.epsilon[q].extend(&self.initial);
+        }
+        nfa
+    }
+
+    /// Renumber the states in breadth-first order from the initial states.
+    ///
+    /// Initial states are numbered first, then successors are numbered
+    /// following ε-transitions before symbols in increasing order, and
+    /// unreachable states come last. Ties between several targets of a same
+    /// transition are broken by the current numbering, so two isomorphic
+    /// automata with a single initial state and at most one target per symbol
+    /// and state get the same numbering.
+    pub fn canonicalize(&self) -> Nfa {
+        let mut order = Vec::with_capacity(self.n_states());
+        let mut new_id: Vec<Option<State>> = vec![None; self.n_states()];
+        let mut visit = |q: State, order: &mut Vec<State>, queue: &mut VecDeque<State>| {
+            if new_id[q].is_none() {
+                new_id[q] = Some(order.len());
+                order.push(q);
+                queue.push_back(q);
+            }
+        };
+
+        let mut queue = VecDeque::new();
+        let mut initial: Vec<State> = self.initial.iter().copied().collect();
+        initial.sort_unstable();
+        for q in initial {
+            visit(q, &mut order, &mut queue);
+        }
+        while let Some(q) = queue.pop_front() {
+            let mut epsilon: Vec<State> = self.epsilon[q].iter().copied().collect();
+            epsilon.sort_unstable();
+            let mut labels: Vec<char> = self.transitions[q].keys().copied().collect();
+            labels.sort_unstable();
+            let successors = labels.into_iter().flat_map(|a| {
+                let mut targets: Vec<State> = self.transitions[q][&a].iter().copied().collect();
+                targets.sort_unstable();
+                targets
+            });
+            for r in epsilon.into_iter().chain(successors) {
+                visit(r, &mut order, &mut queue);
+            }
+        }
+        for q in 0..self.n_states() {
+            visit(q, &mut order, &mut queue);
+        }
+
+        let rename = |q: &State| new_id[*q].unwrap();
+        let mut nfa = Nfa::new(self.n_states());
+        for (new_q, &q) in order.iter().enumerate() {
+            for (&a, targets) in &self.transitions[q] {
+                nfa.transitions[new_q].insert(a, targets.iter().map(rename).collect());
+            }
+            nfa.epsilon[new_q] = self.epsilon[q].iter().map(rename).collect();
+        }
+        nfa.initial = self.initial.iter().map(rename).collect();
+        nfa.finals = self.finals.iter().map(rename).collect();
+        nfa
+    }
+
+    /// Describe the automaton as text, one item per line, in a deterministic order:
+    /// the number of states, the initial states, the final states, then each
+    /// transition as `from label to`, ε-transitions being labeled `ε`.
+    pub fn to_spec(&self) -> String {
+        let list = |states: &HashSet<State>| {
+            let mut states: Vec<State> = states.iter().copied().collect();
+            states.sort_unstable();
+            states.iter().map(|q| format!(" {q}")).collect::<String>()
+        };
+
+        let mut spec = format!(
+            "states {}\ninitial{}\nfinal{}\n",
+            self.n_states(),
+            list(&self.initial),
+            list(&self.finals)
+        );
+        let mut transitions: Vec<(State, Option<char>, State)> = Vec::new();
+        for q in 0..self.n_states() {
+            transitions.extend(self.epsilon[q].iter().map(|&r| (q, None, r)));
+            for (&a, targets) in &self.transitions[q] {
+                transitions.extend(targets.iter().map(|&r| (q, Some(a), r)));
+            }
+        }
+        transitions.sort_unstable();
+        for (q, a, r) in transitions {
+            spec.push_str(&format!("{q} {} {r}\n", a.unwrap_or('ε')));
+        }
+        spec
+    }
+
+    /// Build an equivalent automaton without ε-transitions.
+    ///
+    /// Each state gets the transitions of every state in its ε-closure, and
+    /// becomes final when its ε-closure contains a final state.
+    pub fn remove_epsilon(&self) -> Nfa {
+        let n_states = self.transitions.len();
+        let mut nfa = Nfa::new(n_states);
+        nfa.initial = self.initial.clone();
+        for q in 0..n_states {
+            for p in self.closure(HashSet::from([q])) {
+                if self.finals.contains(&p) {
+                    nfa.finals.insert(q);
+                }
+                for (&a, targets) in &self.transitions[p] {
+                    nfa.transitions[q].entry(a).or_default().extend(targets);
+                }
+            }
+        }
+        nfa
+    }
+
+    /// Build a DFA accepting the words in exactly one of the languages of `self` and `other`.
+    ///
+    /// Its language is the set of words distinguishing the two automata.
+    pub fn symmetric_difference(&self, other: &Nfa) -> Dfa {
+        self.product_dfa(other, |a, b| a != b)
+    }
+
+    /// Build a DFA accepting the words in the language of `self` but not in that of `other`.
+    ///
+    /// This is the product of `self` with the complement of `other`.
+    pub fn difference(&self, other: &Nfa) -> Dfa {
+        self.product_dfa(other, |a, b| a && !b)
+    }
+
+    /// Build the product of the determinizations of `self` and `other`, where
+    /// a word is accepted when `accept` holds of whether `self` and `other`
+    /// accept it.
+    ///
+    /// The product only reads symbols used by `self` or `other`, words using
+    /// other symbols are always rejected, even when `accept(false, false)` holds.
+    pub fn product_with<F: Fn(bool, bool) -> bool>(&self, other: &Nfa, accept: F) -> Nfa {
+        self.product_dfa(other, accept).into()
+    }
+
+    /// Determinize `self` and `other` simultaneously, a product state being
+    /// accepting when `accept` holds of the acceptance of both sides.
+    fn product_dfa<F: Fn(bool, bool) -> bool>(&self, other: &Nfa, accept: F) -> Dfa {
+        let alphabet: BTreeSet<char> = self.alphabet().union(&other.alphabet()).copied().collect();
+        let (dfa, _) = determinize(
+            (self.start_set(), other.start_set()),
+            &alphabet,
+            |(p, q), a| (self.step_sorted(p, a), other.step_sorted(q, a)),
+            |(p, q)| accept(self.accepts_from(p), other.accepts_from(q)),
+        );
+        dfa
+    }
+
+    /// Build an equivalent deterministic automaton by the subset construction.
+    ///
+    /// Only subsets reachable from the initial states are built. The empty
+    /// subset is kept when reachable, so the result has a transition on every
+    /// symbol of the alphabet of `self` from every state.
+    pub fn to_dfa(&self) -> Dfa {
+        self.to_dfa_with_map().0
+    }
+
+    /// Same as [`Nfa::to_dfa`], also returning the subset of states of `self`
+    /// each state of the DFA stands for.
+    pub fn to_dfa_with_map(&self) -> (Dfa, Vec<BTreeSet<State>>) {
+        determinize(
+            self.start_set(),
+            &self.alphabet(),
+            |p, a| self.step_sorted(p, a),
+            |p| self.accepts_from(p),
+        )
+    }
+
+    /// The number of states of the minimal complete DFA recognizing the
+    /// language of `self`, over the alphabet of `self`.
+    pub fn minimal_states(&self) -> usize {
+        self.to_dfa().minimal_states()
+    }
+
+    /// Whether determinizing `self` already gives a minimal DFA, i.e. whether
+    /// [`Nfa::to_dfa`] has as many states as [`Nfa::minimal_states`].
+    pub fn is_minimal(&self) -> bool {
+        let dfa = self.to_dfa();
+        dfa.n_states() == dfa.minimal_states()
+    }
+
+    /// The ε-closure of the initial states.
+    fn start_set(&self) -> BTreeSet<State> {
+        self.closure(self.initial.clone()).into_iter().collect()
+    }
+
+    fn accepts_from(&self, states: &BTreeSet<State>) -> bool {
+        states.iter().any(|q| self.finals.contains(q))
+    }
+}
+
+/// Build the DFA whose states are the values reachable from `start` through
+/// `step`, returning it along with the value of each of its states.
+fn determinize<S, F, A>(start: S, alphabet: &BTreeSet<char>, step: F, accepting: A) -> (Dfa, Vec<S>)
+where
+    S: Clone + Eq + Hash,
+    F: Fn(&S, char) -> S,
+    A: Fn(&S) -> bool,
+{
+    let mut dfa = Dfa {
+        transitions: Vec::new(),
+        initial: 0,
+        finals: HashSet::new(),
+    };
+    let mut ids = HashMap::from([(start.clone(), 0)]);
+    let mut states = vec![start];
+    while let Some(p) = states.get(dfa.transitions.len()).cloned() {
+        if accepting(&p) {
+            dfa.finals.insert(dfa.transitions.len());
+        }
+
+        let mut transitions = HashMap::new();
+        for &a in alphabet {
+            let next_id = *ids.entry(step(&p, a)).or_insert_with_key(|next| {
+                states.push(next.clone());
+                states.len() - 1
+            });
+            transitions.insert(a, next_id);
+        }
+        dfa.transitions.push(transitions);
+    }
+    (dfa, states)
+}
+
+/// A deterministic automaton, missing transitions leading to rejection.
+pub struct Dfa {
+    transitions: Vec<HashMap<char, State>>,
+    initial: State,
+    finals: HashSet<State>,
+}
+
+impl Dfa {
+    pub fn n_states(&self) -> usize {
+        self.transitions.len()
+    }
+
+    /// The number of states of the minimal DFA recognizing the language of `self`,
+    /// computed by partition refinement on the states of `self`.
+    ///
+    /// Every state of `self` is assumed reachable, as in DFAs built by
+    /// [`Nfa::to_dfa`]. Missing transitions are not completed, so the result
+    /// only counts a sink state when `self` has one.
+    pub fn minimal_states(&self) -> usize {
+        let mut alphabet: Vec<char> = self
+            .transitions
+            .iter()
+            .flat_map(|t| t.keys())
+            .copied()
+            .collect();
+        alphabet.sort_unstable();
+        alphabet.dedup();
+
+        let mut class: Vec<usize> = (0..self.n_states())
+            .map(|q| usize::from(self.finals.contains(&q)))
+            .collect();
+        let mut n_classes = class.iter().collect::<HashSet<_>>().len();
+        loop {
+            let mut ids = HashMap::new();
+            let refined: Vec<usize> = (0..self.n_states())
+                .map(|q| {
+                    let signature: Vec<Option<usize>> = alphabet
+                        .iter()
+                        .map(|a| self.transitions[q].get(a).map(|&r| class[r]))
+                        .collect();
+                    let n = ids.len();
+                    *ids.entry((class[q], signature)).or_insert(n)
+                })
+                .collect();
+            if ids.len() == n_classes {
+                return n_classes;
+            }
+            n_classes = ids.len();
+            class = refined;
+        }
+    }
+
+    pub fn accepts(&self, s: &str) -> bool {
+        s.chars()
+            .try_fold(self.initial, |q, a| self.transitions[q].get(&a).copied())
+            .is_some_and(|q| self.finals.contains(&q))
+    }
+}
+
+impl From<Dfa> for Nfa {
+    fn from(dfa: Dfa) -> Self {
+        let mut nfa = Nfa::new(dfa.n_states());
+        for (q, transitions) in dfa.transitions.into_iter().enumerate() {
+            for (a, r) in transitions {
+                nfa.add_transition(q, r, a);
+            }
+        }
+        nfa.add_initial(dfa.initial);
+        nfa.finals = dfa.finals;
+        nfa
     }
 }
 
@@ -68,4 +741,419 @@ mod test {
         assert!(!nfa.accepts("aababa"));
         assert!(!nfa.accepts("abababba"));
     }
+
+    #[test]
+    fn two_accepting_paths() {
+        let mut nfa = Nfa::new(4);
+        nfa.add_transition(0, 1, 'a');
+        nfa.add_transition(0, 2, 'a');
+        nfa.add_transition(1, 3, 'b');
+        nfa.add_transition(2, 3, 'b');
+        nfa.add_initial(0);
+        nfa.add_final(3);
+
+        let paths = nfa.all_accepting_paths("ab");
+        assert_eq!(paths, vec![vec![0, 1, 3], vec![0, 2, 3]]);
+        assert!(nfa.all_accepting_paths("aa").is_empty());
+    }
+
+    #[test]
+    fn right_quotient() {
+        // Words ending in "ab".
+        let mut nfa = Nfa::new(3);
+        nfa.add_transition(0, 0, 'a');
+        nfa.add_transition(0, 0, 'b');
+        nfa.add_transition(0, 1, 'a');
+        nfa.add_transition(1, 2, 'b');
+        nfa.add_initial(0);
+        nfa.add_final(2);
+
+        let quotient = nfa.right_quotient("b");
+        assert!(quotient.accepts("a"));
+        assert!(quotient.accepts("bba"));
+        assert!(quotient.accepts("abaa"));
+        assert!(!quotient.accepts(""));
+        assert!(!quotient.accepts("ab"));
+        assert!(!quotient.accepts("aab"));
+    }
+
+    #[test]
+    fn out_of_range_states() {
+        let mut nfa = Nfa::new(2);
+        assert_eq!(nfa.try_add_transition(0, 1, 'a'), Ok(()));
+        assert_eq!(
+            nfa.try_add_transition(0, 2, 'a'),
+            Err(NfaError::NoSuchState(2))
+        );
+        assert_eq!(
+            nfa.try_add_transition(5, 1, 'a'),
+            Err(NfaError::NoSuchState(5))
+        );
+        assert_eq!(nfa.try_add_initial(3), Err(NfaError::NoSuchState(3)));
+        assert_eq!(nfa.try_add_final(2), Err(NfaError::NoSuchState(2)));
+
+        nfa.add_initial(0);
+        nfa.add_final(1);
+        assert!(nfa.accepts("a"));
+    }
+
+    #[test]
+    #[should_panic]
+    fn add_transition_out_of_range_panics() {
+        let mut nfa = Nfa::new(1);
+        nfa.add_transition(0, 1, 'a');
+    }
+
+    #[test]
+    fn magic_header_bytes() {
+        let mut nfa = Nfa::new(3);
+        nfa.add_byte_transition(0, 1, 0x89);
+        nfa.add_byte_transition(1, 2, 0xff);
+        nfa.add_initial(0);
+        nfa.add_final(2);
+
+        assert!(nfa.accepts_bytes(&[0x89, 0xff]));
+        assert!(!nfa.accepts_bytes(&[0x89]));
+        assert!(!nfa.accepts_bytes(&[0xff, 0x89]));
+        assert!(!nfa.accepts_bytes(&[0x89, 0xff, 0x00]));
+        assert!(!nfa.accepts_bytes(b"PN"));
+    }
+
+    #[test]
+    fn symmetric_difference() {
+        // Words containing an 'a'.
+        let mut contains_a = Nfa::new(2);
+        contains_a.add_transition(0, 0, 'a');
+        contains_a.add_transition(0, 0, 'b');
+        contains_a.add_transition(0, 1, 'a');
+        contains_a.add_transition(1, 1, 'a');
+        contains_a.add_transition(1, 1, 'b');
+        contains_a.add_initial(0);
+        contains_a.add_final(1);
+
+        // Words ending in 'b'.
+        let mut ends_b = Nfa::new(2);
+        ends_b.add_transition(0, 0, 'a');
+        ends_b.add_transition(0, 0, 'b');
+        ends_b.add_transition(0, 1, 'b');
+        ends_b.add_initial(0);
+        ends_b.add_final(1);
+
+        let dfa = contains_a.symmetric_difference(&ends_b);
+        for s in [
+            "", "a", "b", "ab", "ba", "bb", "aab", "bba", "babab", "c", "ac",
+        ] {
+            assert_eq!(
+                dfa.accepts(s),
+                contains_a.accepts(s) ^ ends_b.accepts(s),
+                "on {s:?}"
+            );
+        }
+        assert!(dfa.accepts("a"));
+        assert!(dfa.accepts("bb"));
+        assert!(!dfa.accepts("ab"));
+    }
+
+    #[test]
+    fn remove_epsilon() {
+        // a?b
+        let mut nfa = Nfa::new(3);
+        nfa.add_transition(0, 1, 'a');
+        nfa.add_epsilon_transition(0, 1);
+        nfa.add_transition(1, 2, 'b');
+        nfa.add_initial(0);
+        nfa.add_final(2);
+
+        let free = nfa.remove_epsilon();
+        assert!(free.epsilon.iter().all(HashSet::is_empty));
+        for s in ["ab", "b", "", "a", "aab", "bb", "abb"] {
+            assert_eq!(free.accepts(s), nfa.accepts(s), "on {s:?}");
+        }
+        assert!(free.accepts("ab"));
+        assert!(free.accepts("b"));
+        assert!(!free.accepts("a"));
+    }
+
+    #[test]
+    fn epsilon_closure_accepts() {
+        let mut nfa = Nfa::new(3);
+        nfa.add_epsilon_transition(0, 1);
+        nfa.add_epsilon_transition(1, 2);
+        nfa.add_epsilon_transition(2, 0);
+        nfa.add_transition(2, 2, 'a');
+        nfa.add_initial(0);
+        nfa.add_final(1);
+
+        assert!(nfa.accepts(""));
+        assert!(nfa.accepts("aaa"));
+        assert!(!nfa.accepts("b"));
+    }
+
+    #[test]
+    fn minimal_states() {
+        let mut parity = Nfa::new(2);
+        parity.add_transition(0, 1, 'a');
+        parity.add_transition(1, 0, 'a');
+        parity.add_transition(0, 0, 'b');
+        parity.add_transition(1, 1, 'b');
+        parity.add_initial(0);
+        parity.add_final(0);
+        assert_eq!(parity.minimal_states(), 2);
+
+        // Same language, with two redundant copies of each state.
+        let mut redundant = Nfa::new(4);
+        redundant.add_transition(0, 1, 'a');
+        redundant.add_transition(1, 2, 'a');
+        redundant.add_transition(2, 3, 'a');
+        redundant.add_transition(3, 0, 'a');
+        for q in 0..4 {
+            redundant.add_transition(q, q, 'b');
+        }
+        redundant.add_initial(0);
+        redundant.add_final(0);
+        redundant.add_final(2);
+        assert_eq!(redundant.to_dfa().n_states(), 4);
+        assert_eq!(redundant.minimal_states(), 2);
+    }
+
+    #[test]
+    fn transition_matrices() {
+        let mut nfa = Nfa::new(2);
+        nfa.add_transition(0, 1, 'a');
+        nfa.add_transition(1, 0, 'a');
+        nfa.add_transition(0, 0, 'b');
+        nfa.add_transition(1, 1, 'b');
+
+        let a = nfa.transition_matrix('a');
+        assert_eq!(a, vec![vec![false, true], vec![true, false]]);
+        let b = nfa.transition_matrix('b');
+        assert!(b[0][0] && b[1][1]);
+        assert!(!b[0][1] && !b[1][0]);
+        assert_eq!(nfa.transition_matrix('c'), vec![vec![false; 2]; 2]);
+    }
+
+    #[test]
+    fn accepts_iter() {
+        let mut nfa = Nfa::new(2);
+        nfa.add_transition(0, 1, 'a');
+        nfa.add_transition(1, 0, 'b');
+        nfa.add_initial(0);
+        nfa.add_final(0);
+
+        assert_eq!(nfa.accepts_iter("abab".chars()), nfa.accepts("abab"));
+        assert!(nfa.accepts_iter(vec!['a', 'b']));
+        assert_eq!(
+            nfa.accepts_iter(vec!['a', 'b'].into_iter()),
+            nfa.accepts("ab")
+        );
+        assert!(!nfa.accepts_iter(std::iter::repeat_n('a', 3)));
+    }
+
+    #[test]
+    fn dfa_subset_map() {
+        let mut nfa = Nfa::new(2);
+        nfa.add_transition(0, 1, 'a');
+        nfa.add_transition(1, 0, 'a');
+        nfa.add_transition(0, 0, 'b');
+        nfa.add_transition(1, 1, 'b');
+        nfa.add_initial(0);
+        nfa.add_final(0);
+
+        let (dfa, subsets) = nfa.to_dfa_with_map();
+        assert_eq!(dfa.n_states(), subsets.len());
+        assert_eq!(subsets[0], BTreeSet::from([0]));
+        assert!(subsets.contains(&BTreeSet::from([1])));
+        assert_eq!(subsets.len(), 2);
+    }
+
+    #[test]
+    fn union_without_epsilon() {
+        // a(ba)*
+        let mut left = Nfa::new(2);
+        left.add_transition(0, 1, 'a');
+        left.add_transition(1, 0, 'b');
+        left.add_initial(0);
+        left.add_final(1);
+
+        // b+
+        let mut right = Nfa::new(2);
+        right.add_transition(0, 1, 'b');
+        right.add_transition(1, 1, 'b');
+        right.add_initial(0);
+        right.add_final(1);
+
+        let union = left.union_no_epsilon(&right);
+        assert_eq!(union.n_states(), 4);
+        assert_eq!(union.initial, HashSet::from([0, 2]));
+        assert!(union.epsilon.iter().all(HashSet::is_empty));
+        for s in ["a", "aba", "b", "bbb", "", "ab", "ba", "abb"] {
+            assert_eq!(
+                union.accepts(s),
+                left.accepts(s) || right.accepts(s),
+                "on {s:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn product_with() {
+        // Words with an even number of 'a'.
+        let mut even_a = Nfa::new(2);
+        even_a.add_transition(0, 1, 'a');
+        even_a.add_transition(1, 0, 'a');
+        even_a.add_transition(0, 0, 'b');
+        even_a.add_transition(1, 1, 'b');
+        even_a.add_initial(0);
+        even_a.add_final(0);
+
+        // Words ending in 'b'.
+        let mut ends_b = Nfa::new(2);
+        ends_b.add_transition(0, 0, 'a');
+        ends_b.add_transition(0, 0, 'b');
+        ends_b.add_transition(0, 1, 'b');
+        ends_b.add_initial(0);
+        ends_b.add_final(1);
+
+        let intersection = even_a.product_with(&ends_b, |a, b| a && b);
+        let xor = even_a.product_with(&ends_b, |a, b| a ^ b);
+        let symmetric_difference = even_a.symmetric_difference(&ends_b);
+        for s in [
+            "", "a", "b", "ab", "aab", "aabb", "ba", "bab", "abab", "aaa",
+        ] {
+            assert_eq!(
+                intersection.accepts(s),
+                even_a.accepts(s) && ends_b.accepts(s),
+                "on {s:?}"
+            );
+            assert_eq!(xor.accepts(s), symmetric_difference.accepts(s), "on {s:?}");
+        }
+    }
+
+    #[test]
+    fn difference() {
+        let contains = |c: char| {
+            let mut nfa = Nfa::new(2);
+            for q in 0..2 {
+                nfa.add_transition(q, q, 'a');
+                nfa.add_transition(q, q, 'b');
+            }
+            nfa.add_transition(0, 1, c);
+            nfa.add_initial(0);
+            nfa.add_final(1);
+            nfa
+        };
+        let (contains_a, contains_b) = (contains('a'), contains('b'));
+
+        let dfa = contains_a.difference(&contains_b);
+        for s in ["a", "aa", "aaa"] {
+            assert!(dfa.accepts(s), "on {s:?}");
+        }
+        for s in ["", "b", "ab", "ba", "bab", "aabaa"] {
+            assert!(!dfa.accepts(s), "on {s:?}");
+        }
+    }
+
+    #[test]
+    fn canonicalize() {
+        // (ab)* with an unreachable state, numbered in two different ways.
+        let mut first = Nfa::new(3);
+        first.add_transition(0, 1, 'a');
+        first.add_transition(1, 0, 'b');
+        first.add_transition(2, 2, 'c');
+        first.add_initial(0);
+        first.add_final(0);
+
+        let mut second = Nfa::new(3);
+        second.add_transition(2, 0, 'a');
+        second.add_transition(0, 2, 'b');
+        second.add_transition(1, 1, 'c');
+        second.add_initial(2);
+        second.add_final(2);
+
+        assert_ne!(first.to_spec(), second.to_spec());
+        let spec = first.canonicalize().to_spec();
+        assert_eq!(spec, second.canonicalize().to_spec());
+        assert_eq!(spec, "states 3\ninitial 0\nfinal 0\n0 a 1\n1 b 0\n2 c 2\n");
+    }
+
+    #[test]
+    fn plus() {
+        let mut ab = Nfa::new(3);
+        ab.add_transition(0, 1, 'a');
+        ab.add_transition(1, 2, 'b');
+        ab.add_initial(0);
+        ab.add_final(2);
+
+        let plus = ab.plus();
+        assert!(plus.accepts("ab"));
+        assert!(plus.accepts("abab"));
+        assert!(plus.accepts("ababab"));
+        assert!(!plus.accepts(""));
+        assert!(!plus.accepts("aba"));
+        assert!(!plus.accepts("abb"));
+    }
+
+    #[test]
+    fn dead_states() {
+        let mut nfa = Nfa::new(5);
+        nfa.add_transition(0, 1, 'a');
+        nfa.add_transition(0, 2, 'b');
+        nfa.add_transition(2, 3, 'a');
+        nfa.add_transition(3, 3, 'a');
+        nfa.add_transition(4, 1, 'a');
+        nfa.add_initial(0);
+        nfa.add_final(1);
+
+        assert_eq!(nfa.reachable_states(), HashSet::from([0, 1, 2, 3]));
+        assert_eq!(nfa.co_reachable_states(), HashSet::from([0, 1, 4]));
+        assert_eq!(nfa.dead_states(), HashSet::from([2, 3]));
+    }
+
+    #[test]
+    fn scan_tokens() {
+        // Identifiers [a-z]+ and numbers [0-9]+.
+        let mut nfa = Nfa::new(3);
+        for c in 'a'..='z' {
+            nfa.add_transition(0, 1, c);
+            nfa.add_transition(1, 1, c);
+        }
+        for c in '0'..='9' {
+            nfa.add_transition(0, 2, c);
+            nfa.add_transition(2, 2, c);
+        }
+        nfa.add_initial(0);
+        nfa.add_final(1);
+        nfa.add_final(2);
+
+        assert_eq!(nfa.scan("abc123"), vec!["abc", "123"]);
+        assert_eq!(nfa.scan("x1 + yz"), vec!["x", "1", "yz"]);
+        assert!(nfa.scan("").is_empty());
+        assert!(nfa.scan("+-").is_empty());
+    }
+
+    #[test]
+    fn is_minimal() {
+        // Even number of 'a', with two redundant copies of each state.
+        let mut redundant = Nfa::new(4);
+        redundant.add_transition(0, 1, 'a');
+        redundant.add_transition(1, 2, 'a');
+        redundant.add_transition(2, 3, 'a');
+        redundant.add_transition(3, 0, 'a');
+        for q in 0..4 {
+            redundant.add_transition(q, q, 'b');
+        }
+        redundant.add_initial(0);
+        redundant.add_final(0);
+        redundant.add_final(2);
+        assert!(!redundant.is_minimal());
+
+        let mut minimized = Nfa::new(2);
+        minimized.add_transition(0, 1, 'a');
+        minimized.add_transition(1, 0, 'a');
+        minimized.add_transition(0, 0, 'b');
+        minimized.add_transition(1, 1, 'b');
+        minimized.add_initial(0);
+        minimized.add_final(0);
+        assert!(minimized.is_minimal());
+    }
 }
@@ -1,20 +1,1339 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt;
 
+/// Subtrees with fewer nodes than this are evaluated sequentially by
+/// [`ArithmeticExpr::evaluate_parallel`].
+#[cfg(feature = "rayon")]
+pub const PARALLEL_THRESHOLD: usize = 4096;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum ArithmeticExpr {
-    // Define the structure of an arithmetic expression
+    Constant(i32),
+    Variable(usize),
+    Operation {
+        op: Op,
+        left: Box<ArithmeticExpr>,
+        right: Box<ArithmeticExpr>,
+    },
+    Unary {
+        func: UnaryFunc,
+        arg: Box<ArithmeticExpr>,
+    },
+}
+
+/// Binary operators, written `+`, `-`, `*`, `/`, `%` and `^` in RPN.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Op {
+    Add,
+    Sub,
+    Mul,
+    /// Integer division, truncating toward zero.
+    Div,
+    /// Remainder of the truncating division, so it has the sign of the
+    /// left operand: `-7 % 3 == -1`.
+    Mod,
+    /// Exponentiation. The exponent must not be negative, since the result
+    /// would not be an integer, and overflows are reported rather than wrapped.
+    Pow,
+}
+
+/// Unary functions, written `abs`, `neg` and `sgn` in RPN.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum UnaryFunc {
+    Abs,
+    Neg,
+    Sgn,
+}
+
+/// Errors reported by [`ArithmeticExpr::from_rpn`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RpnParseError {
+    /// The input has no tokens.
+    EmptyExpression,
+    /// A token is neither an operator, a function, a variable nor an integer.
+    UnexpectedToken(String),
+    /// An operator or function is missing operands.
+    StackUnderflow,
+    /// The input holds several expressions: this many are left over besides
+    /// the last one.
+    TrailingTokens(usize),
+    /// A token starts with `x_` but is not followed by a variable index.
+    InvalidVariable(String),
+}
+
+impl fmt::Display for RpnParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RpnParseError::EmptyExpression => write!(f, "empty expression"),
+            RpnParseError::UnexpectedToken(token) => write!(f, "unexpected token: {token}"),
+            RpnParseError::StackUnderflow => write!(f, "missing operand"),
+            RpnParseError::TrailingTokens(n) => write!(f, "{n} unused operands"),
+            RpnParseError::InvalidVariable(token) => write!(f, "invalid variable: {token}"),
+        }
+    }
+}
+
+impl std::error::Error for RpnParseError {}
+
+/// Errors reported by [`ArithmeticExpr::parse`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseError {
+    /// The input ends in the middle of an expression, or is empty.
+    UnexpectedEnd,
+    /// A token is misplaced, or is not a valid token at all.
+    UnexpectedToken(String),
+    /// A word starts with `x_` but is not followed by a variable index.
+    InvalidVariable(String),
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::UnexpectedEnd => write!(f, "unexpected end of input"),
+            ParseError::UnexpectedToken(token) => write!(f, "unexpected token: {token}"),
+            ParseError::InvalidVariable(token) => write!(f, "invalid variable: {token}"),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// A token of the infix syntax accepted by [`ArithmeticExpr::parse`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Token {
+    /// A literal, which is never negative: `-` is a separate token.
+    Constant(u32),
+    Variable(usize),
+    Op(Op),
+    Func(UnaryFunc),
+    Open,
+    Close,
+}
+
+/// Binding power of unary functions and of the prefix `-` in infix notation:
+/// above `*`, but below `^` so that `-2 ^ 2 == -4`.
+const UNARY_PRECEDENCE: u8 = 3;
+
+/// Errors reported by [`ArithmeticExpr::evaluate`] and its variants.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EvalError {
+    /// The variable `x_i` has no value.
+    UndefinedVariable(usize),
+    /// The right operand of a `/` or `%` is zero.
+    DivisionByZero,
+    /// The exponent of a `^` is negative.
+    NegativeExponent(i32),
+    /// The result of an operation or function does not fit in an `i32`.
+    Overflow,
+    /// In [`ArithmeticExpr::evaluate_columns`], the column of `x_var` has only
+    /// `len` values, fewer than the number of rows.
+    ShortColumn { var: usize, len: usize },
+}
+
+impl fmt::Display for EvalError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EvalError::UndefinedVariable(i) => write!(f, "undefined variable x_{i}"),
+            EvalError::DivisionByZero => write!(f, "division by zero"),
+            EvalError::NegativeExponent(exp) => write!(f, "negative exponent {exp}"),
+            EvalError::Overflow => write!(f, "overflow"),
+            EvalError::ShortColumn { var, len } => {
+                write!(f, "column for x_{var} has only {len} rows")
+            }
+        }
+    }
+}
+
+impl std::error::Error for EvalError {}
+
+/// Values of the subexpressions evaluated by [`ArithmeticExpr::evaluate_cached`].
+///
+/// Each distinct subexpression gets a number the first time it is evaluated.
+/// A node is then looked up by its operator and the numbers of its children,
+/// so the lookup does not hash the whole subtree.
+#[derive(Debug, Clone, Default)]
+pub struct EvalCache {
+    ids: HashMap<CacheKey, usize>,
+    /// The value of each subexpression, indexed by its number.
+    values: Vec<i32>,
+}
+
+/// A node of an expression, its children replaced by their numbers in an
+/// [`EvalCache`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum CacheKey {
+    Constant(i32),
+    Variable(usize),
+    Operation(Op, usize, usize),
+    Unary(UnaryFunc, usize),
+}
+
+impl EvalCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of distinct subexpressions evaluated, each of them only once.
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    /// Forget all values, which is needed before evaluating with other variables.
+    pub fn clear(&mut self) {
+        self.ids.clear();
+        self.values.clear();
+    }
+
+    /// The value of `expr`, if it was evaluated with this cache.
+    pub fn get(&self, expr: &ArithmeticExpr) -> Option<i32> {
+        self.id_of(expr).map(|id| self.values[id])
+    }
+
+    fn id_of(&self, expr: &ArithmeticExpr) -> Option<usize> {
+        let key = match expr {
+            ArithmeticExpr::Constant(c) => CacheKey::Constant(*c),
+            ArithmeticExpr::Variable(i) => CacheKey::Variable(*i),
+            ArithmeticExpr::Operation { op, left, right } => {
+                CacheKey::Operation(*op, self.id_of(left)?, self.id_of(right)?)
+            }
+            ArithmeticExpr::Unary { func, arg } => CacheKey::Unary(*func, self.id_of(arg)?),
+        };
+        self.ids.get(&key).copied()
+    }
+}
+
+/// An operand of a three-address code instruction, see [`ArithmeticExpr::to_tac`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operand {
+    Constant(i32),
+    Variable(usize),
+    /// The temporary `t<n>`.
+    Temp(usize),
+}
+
+/// A three-address code instruction, storing its result in the temporary `t<dest>`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TacInstr {
+    Copy {
+        dest: usize,
+        src: Operand,
+    },
+    Binary {
+        dest: usize,
+        op: Op,
+        left: Operand,
+        right: Operand,
+    },
+    Unary {
+        dest: usize,
+        func: UnaryFunc,
+        arg: Operand,
+    },
+}
+
+/// Writes the expression in the infix notation of [`ArithmeticExpr::parse`],
+/// with only the parentheses required by precedence and associativity.
+impl fmt::Display for ArithmeticExpr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ArithmeticExpr::Constant(c) => write!(f, "{c}"),
+            ArithmeticExpr::Variable(i) => write!(f, "x_{i}"),
+            ArithmeticExpr::Operation { op, left, right } => {
+                let p = op.precedence();
+                let (left_min, right_min) = if op.is_right_associative() {
+                    (p + 1, p)
+                } else {
+                    (p, p + 1)
+                };
+                left.fmt_operand(f, left_min)?;
+                write!(f, " {} ", op.symbol())?;
+                right.fmt_operand(f, right_min)
+            }
+            ArithmeticExpr::Unary {
+                func: UnaryFunc::Neg,
+                arg,
+            } => {
+                write!(f, "-")?;
+                arg.fmt_operand(f, UNARY_PRECEDENCE)
+            }
+            ArithmeticExpr::Unary { func, arg } => write!(f, "{}({arg})", func.name()),
+        }
+    }
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Operand::Constant(c) => write!(f, "{c}"),
+            Operand::Variable(i) => write!(f, "x_{i}"),
+            Operand::Temp(t) => write!(f, "t{t}"),
+        }
+    }
+}
+
+impl fmt::Display for TacInstr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TacInstr::Copy { dest, src } => write!(f, "t{dest} = {src}"),
+            TacInstr::Binary {
+                dest,
+                op,
+                left,
+                right,
+            } => write!(f, "t{dest} = {left} {} {right}", op.symbol()),
+            TacInstr::Unary { dest, func, arg } => write!(f, "t{dest} = {} {arg}", func.name()),
+        }
+    }
+}
+
+/// Cost of each kind of operator, see [`ArithmeticExpr::cost`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OpCosts {
+    pub add: u64,
+    pub sub: u64,
+    pub mul: u64,
+    /// Cost of a division or remainder.
+    pub div: u64,
+    pub pow: u64,
+    /// Cost of any unary function.
+    pub unary: u64,
+}
+
+impl OpCosts {
+    fn of(&self, op: Op) -> u64 {
+        match op {
+            Op::Add => self.add,
+            Op::Sub => self.sub,
+            Op::Mul => self.mul,
+            Op::Div | Op::Mod => self.div,
+            Op::Pow => self.pow,
+        }
+    }
+}
+
+impl Op {
+    fn from_token(token: &str) -> Option<Self> {
+        match token {
+            "+" => Some(Op::Add),
+            "-" => Some(Op::Sub),
+            "*" => Some(Op::Mul),
+            "/" => Some(Op::Div),
+            "%" => Some(Op::Mod),
+            "^" => Some(Op::Pow),
+            _ => None,
+        }
+    }
+
+    fn symbol(self) -> &'static str {
+        match self {
+            Op::Add => "+",
+            Op::Sub => "-",
+            Op::Mul => "*",
+            Op::Div => "/",
+            Op::Mod => "%",
+            Op::Pow => "^",
+        }
+    }
+
+    /// Binding power of the operator in infix notation.
+    fn precedence(self) -> u8 {
+        match self {
+            Op::Add | Op::Sub => 1,
+            Op::Mul | Op::Div | Op::Mod => 2,
+            Op::Pow => 4,
+        }
+    }
+
+    /// Whether `a op b op c` reads as `a op (b op c)` in infix notation.
+    fn is_right_associative(self) -> bool {
+        self == Op::Pow
+    }
+
+    fn apply(self, l: i32, r: i32) -> Result<i32, EvalError> {
+        match self {
+            Op::Add => l.checked_add(r).ok_or(EvalError::Overflow),
+            Op::Sub => l.checked_sub(r).ok_or(EvalError::Overflow),
+            Op::Mul => l.checked_mul(r).ok_or(EvalError::Overflow),
+            Op::Div | Op::Mod if r == 0 => Err(EvalError::DivisionByZero),
+            Op::Div => l.checked_div(r).ok_or(EvalError::Overflow),
+            Op::Mod => l.checked_rem(r).ok_or(EvalError::Overflow),
+            Op::Pow => {
+                let exp = u32::try_from(r).map_err(|_| EvalError::NegativeExponent(r))?;
+                l.checked_pow(exp).ok_or(EvalError::Overflow)
+            }
+        }
+    }
+
+    fn apply_f64(self, l: f64, r: f64) -> f64 {
+        match self {
+            Op::Add => l + r,
+            Op::Sub => l - r,
+            Op::Mul => l * r,
+            Op::Div => l / r,
+            Op::Mod => l % r,
+            Op::Pow => l.powf(r),
+        }
+    }
+}
+
+impl UnaryFunc {
+    fn from_token(token: &str) -> Option<Self> {
+        match token {
+            "abs" => Some(UnaryFunc::Abs),
+            "neg" => Some(UnaryFunc::Neg),
+            "sgn" => Some(UnaryFunc::Sgn),
+            _ => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            UnaryFunc::Abs => "abs",
+            UnaryFunc::Neg => "neg",
+            UnaryFunc::Sgn => "sgn",
+        }
+    }
+
+    fn apply(self, x: i32) -> Result<i32, EvalError> {
+        match self {
+            UnaryFunc::Abs => x.checked_abs().ok_or(EvalError::Overflow),
+            UnaryFunc::Neg => x.checked_neg().ok_or(EvalError::Overflow),
+            UnaryFunc::Sgn => Ok(x.signum()),
+        }
+    }
+
+    fn apply_f64(self, x: f64) -> f64 {
+        match self {
+            UnaryFunc::Abs => x.abs(),
+            UnaryFunc::Neg => -x,
+            UnaryFunc::Sgn if x == 0.0 => 0.0,
+            UnaryFunc::Sgn => x.signum(),
+        }
+    }
 }
 
 impl ArithmeticExpr {
-    pub fn from_rpn(rpn: &str) -> Self {
-        todo!("Construct an arithmetic expression from a Reverse Polish Notation string")
+    /// Parse an expression in Reverse Polish Notation, with tokens separated
+    /// by whitespace: integer constants, variables `x_0`, `x_1`, ..., the
+    /// binary operators of [`Op`] and the unary functions of [`UnaryFunc`].
+    pub fn from_rpn(rpn: &str) -> Result<Self, RpnParseError> {
+        let mut stack = Vec::new();
+        for token in rpn.split_whitespace() {
+            let expr = if let Some(op) = Op::from_token(token) {
+                let right = Box::new(stack.pop().ok_or(RpnParseError::StackUnderflow)?);
+                let left = Box::new(stack.pop().ok_or(RpnParseError::StackUnderflow)?);
+                ArithmeticExpr::Operation { op, left, right }
+            } else if let Some(func) = UnaryFunc::from_token(token) {
+                let arg = Box::new(stack.pop().ok_or(RpnParseError::StackUnderflow)?);
+                ArithmeticExpr::Unary { func, arg }
+            } else if let Some(index) = token.strip_prefix("x_") {
+                let index = index
+                    .parse()
+                    .map_err(|_| RpnParseError::InvalidVariable(token.to_string()))?;
+                ArithmeticExpr::Variable(index)
+            } else {
+                let c = token
+                    .parse()
+                    .map_err(|_| RpnParseError::UnexpectedToken(token.to_string()))?;
+                ArithmeticExpr::Constant(c)
+            };
+            stack.push(expr);
+        }
+
+        let expr = stack.pop().ok_or(RpnParseError::EmptyExpression)?;
+        if !stack.is_empty() {
+            return Err(RpnParseError::TrailingTokens(stack.len()));
+        }
+        Ok(expr)
+    }
+
+    /// Parse an expression in the usual infix notation, such as
+    /// `3 + 4 * (x_1 - 2)`.
+    ///
+    /// `^` binds tighter than unary functions and the prefix `-` (a negation),
+    /// which bind tighter than `*`, `/` and `%`, which bind tighter than `+`
+    /// and `-`. `^` is right-associative, the other operators are
+    /// left-associative. Functions take their argument with or without
+    /// parentheses: `abs(x_1)` or `abs x_1`. A parenthesized argument ends at
+    /// the closing parenthesis, so `abs(x_1) ^ 2` squares the absolute value,
+    /// while `abs x_1 ^ 2` reads as `abs(x_1 ^ 2)`, like `-x_1 ^ 2`.
+    ///
+    /// `-2147483648` is read as the constant `i32::MIN` rather than as a
+    /// negation, since `2147483648` does not fit in an `i32`.
+    pub fn parse(s: &str) -> Result<Self, ParseError> {
+        let tokens = tokenize(s)?;
+        let mut pos = 0;
+        let expr = Self::parse_infix(&tokens, &mut pos, 0)?;
+        match tokens.get(pos) {
+            None => Ok(expr),
+            Some((_, text)) => Err(ParseError::UnexpectedToken(text.to_string())),
+        }
+    }
+
+    /// Parse the longest expression starting at `tokens[*pos]` whose operators
+    /// not enclosed in parentheses have a precedence of at least `min_precedence`.
+    fn parse_infix(
+        tokens: &[(Token, &str)],
+        pos: &mut usize,
+        min_precedence: u8,
+    ) -> Result<Self, ParseError> {
+        let &(token, text) = tokens.get(*pos).ok_or(ParseError::UnexpectedEnd)?;
+        *pos += 1;
+        let mut expr = match token {
+            Token::Constant(c) => ArithmeticExpr::Constant(
+                i32::try_from(c).map_err(|_| ParseError::UnexpectedToken(text.to_string()))?,
+            ),
+            Token::Variable(i) => ArithmeticExpr::Variable(i),
+            Token::Open => {
+                let expr = Self::parse_infix(tokens, pos, 0)?;
+                match tokens.get(*pos) {
+                    Some((Token::Close, _)) => *pos += 1,
+                    Some((_, text)) => return Err(ParseError::UnexpectedToken(text.to_string())),
+                    None => return Err(ParseError::UnexpectedEnd),
+                }
+                expr
+            }
+            Token::Func(func) => {
+                // No operator binds tightly enough to extend a parenthesized
+                // argument past its closing parenthesis.
+                let min_precedence = match tokens.get(*pos) {
+                    Some((Token::Open, _)) => u8::MAX,
+                    _ => UNARY_PRECEDENCE,
+                };
+                ArithmeticExpr::Unary {
+                    func,
+                    arg: Box::new(Self::parse_infix(tokens, pos, min_precedence)?),
+                }
+            }
+            // The only way to write `i32::MIN`, whose opposite is not an `i32`.
+            Token::Op(Op::Sub)
+                if tokens.get(*pos).map(|&(token, _)| token)
+                    == Some(Token::Constant(i32::MIN.unsigned_abs())) =>
+            {
+                *pos += 1;
+                ArithmeticExpr::Constant(i32::MIN)
+            }
+            Token::Op(Op::Sub) => ArithmeticExpr::Unary {
+                func: UnaryFunc::Neg,
+                arg: Box::new(Self::parse_infix(tokens, pos, UNARY_PRECEDENCE)?),
+            },
+            Token::Op(_) | Token::Close => {
+                return Err(ParseError::UnexpectedToken(text.to_string()))
+            }
+        };
+
+        while let Some(&(Token::Op(op), _)) = tokens.get(*pos) {
+            if op.precedence() < min_precedence {
+                break;
+            }
+            *pos += 1;
+            let right_precedence = if op.is_right_associative() {
+                op.precedence()
+            } else {
+                op.precedence() + 1
+            };
+            let right = Self::parse_infix(tokens, pos, right_precedence)?;
+            expr = ArithmeticExpr::Operation {
+                op,
+                left: Box::new(expr),
+                right: Box::new(right),
+            };
+        }
+        Ok(expr)
     }
 
     pub fn size(&self) -> usize {
-        todo!("Compute the size of the regex")
+        match self {
+            ArithmeticExpr::Constant(_) | ArithmeticExpr::Variable(_) => 1,
+            ArithmeticExpr::Operation { left, right, .. } => 1 + left.size() + right.size(),
+            ArithmeticExpr::Unary { arg, .. } => 1 + arg.size(),
+        }
+    }
+
+    /// Flatten chains of additions and of multiplications into a canonical
+    /// left-deep shape: `a + (b + (c + d))` becomes `((a + b) + c) + d`.
+    ///
+    /// A chain keeps its operands in left-to-right order, so it can be read as a
+    /// single n-ary operation by walking down its left spine.
+    pub fn flatten(self) -> ArithmeticExpr {
+        match self {
+            ArithmeticExpr::Operation {
+                op: op @ (Op::Add | Op::Mul),
+                ..
+            } => {
+                let mut operands = Vec::new();
+                self.collect_chain(op, &mut operands);
+                operands
+                    .into_iter()
+                    .reduce(|left, right| ArithmeticExpr::Operation {
+                        op,
+                        left: Box::new(left),
+                        right: Box::new(right),
+                    })
+                    .unwrap()
+            }
+            ArithmeticExpr::Operation { op, left, right } => ArithmeticExpr::Operation {
+                op,
+                left: Box::new(left.flatten()),
+                right: Box::new(right.flatten()),
+            },
+            ArithmeticExpr::Unary { func, arg } => ArithmeticExpr::Unary {
+                func,
+                arg: Box::new(arg.flatten()),
+            },
+            ArithmeticExpr::Constant(_) | ArithmeticExpr::Variable(_) => self,
+        }
+    }
+
+    /// Push the flattened operands of the maximal chain of `op` rooted at `self` to `operands`.
+    fn collect_chain(self, op: Op, operands: &mut Vec<ArithmeticExpr>) {
+        match self {
+            ArithmeticExpr::Operation {
+                op: node_op,
+                left,
+                right,
+            } if node_op == op => {
+                left.collect_chain(op, operands);
+                right.collect_chain(op, operands);
+            }
+            _ => operands.push(self.flatten()),
+        }
+    }
+
+    /// Rewrite every subtraction `a - b` as `a + neg(b)`, leaving only
+    /// additions and multiplications as binary operations.
+    pub fn to_add_neg_form(self) -> ArithmeticExpr {
+        match self {
+            ArithmeticExpr::Constant(_) | ArithmeticExpr::Variable(_) => self,
+            ArithmeticExpr::Operation { op, left, right } => {
+                let left = Box::new(left.to_add_neg_form());
+                let right = Box::new(right.to_add_neg_form());
+                match op {
+                    Op::Sub => ArithmeticExpr::Operation {
+                        op: Op::Add,
+                        left,
+                        right: Box::new(ArithmeticExpr::Unary {
+                            func: UnaryFunc::Neg,
+                            arg: right,
+                        }),
+                    },
+                    _ => ArithmeticExpr::Operation { op, left, right },
+                }
+            }
+            ArithmeticExpr::Unary { func, arg } => ArithmeticExpr::Unary {
+                func,
+                arg: Box::new(arg.to_add_neg_form()),
+            },
+        }
+    }
+
+    /// Fold the subexpressions without variables into constants, and remove
+    /// additions and subtractions of `0` and multiplications by `1`.
+    ///
+    /// A multiplication by `0` is replaced by `0` only if the other operand
+    /// cannot fail to evaluate, so that an evaluation error is never turned
+    /// into a value. For the same reason, subexpressions whose evaluation fails,
+    /// such as overflowing ones, are left as they are.
+    pub fn simplify(self) -> ArithmeticExpr {
+        use ArithmeticExpr::Constant;
+
+        match self {
+            ArithmeticExpr::Constant(_) | ArithmeticExpr::Variable(_) => self,
+            ArithmeticExpr::Operation { op, left, right } => {
+                let left = left.simplify();
+                let right = right.simplify();
+                match (op, &left, &right) {
+                    (_, Constant(l), Constant(r)) if op.apply(*l, *r).is_ok() => {
+                        Constant(op.apply(*l, *r).unwrap())
+                    }
+                    (Op::Add, Constant(0), _) | (Op::Mul, Constant(1), _) => right,
+                    (Op::Add | Op::Sub, _, Constant(0)) | (Op::Mul, _, Constant(1)) => left,
+                    (Op::Mul, Constant(0), e) | (Op::Mul, e, Constant(0)) if !e.may_fail() => {
+                        Constant(0)
+                    }
+                    _ => ArithmeticExpr::Operation {
+                        op,
+                        left: Box::new(left),
+                        right: Box::new(right),
+                    },
+                }
+            }
+            ArithmeticExpr::Unary { func, arg } => match arg.simplify() {
+                Constant(c) if func.apply(c).is_ok() => Constant(func.apply(c).unwrap()),
+                arg => ArithmeticExpr::Unary {
+                    func,
+                    arg: Box::new(arg),
+                },
+            },
+        }
+    }
+
+    /// The partial derivative of the expression with respect to `x_var`,
+    /// unsimplified: pair it with [`ArithmeticExpr::simplify`].
+    ///
+    /// `abs` is differentiated as `sgn` and `sgn` as `0`, ignoring the point
+    /// where their argument is zero. The power rule `(a ^ e)' = e * a ^ (e - 1) * a'`
+    /// applies to any exponent `e` without `x_var`. When `e` is not a constant,
+    /// the derivative fails to evaluate where `e` is zero, on `a ^ -1`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a `/` or `%` depends on `x_var`, or the exponent of a `^`
+    /// does, as they have no derivative in integer arithmetic.
+    pub fn derivative(&self, var: usize) -> ArithmeticExpr {
+        match self {
+            ArithmeticExpr::Constant(_) => ArithmeticExpr::Constant(0),
+            ArithmeticExpr::Variable(i) => ArithmeticExpr::Constant((*i == var).into()),
+            ArithmeticExpr::Operation { op, left, right } => match op {
+                Op::Add | Op::Sub => binary(*op, left.derivative(var), right.derivative(var)),
+                Op::Mul => binary(
+                    Op::Add,
+                    binary(Op::Mul, left.derivative(var), (**right).clone()),
+                    binary(Op::Mul, (**left).clone(), right.derivative(var)),
+                ),
+                _ if !self.variables().contains(&var) => ArithmeticExpr::Constant(0),
+                Op::Pow if right.variables().contains(&var) => {
+                    panic!("`^` has no derivative when its exponent depends on x_{var}")
+                }
+                Op::Pow => {
+                    let reduced = match **right {
+                        ArithmeticExpr::Constant(0) => return ArithmeticExpr::Constant(0),
+                        ArithmeticExpr::Constant(n) => {
+                            n.checked_sub(1).map(ArithmeticExpr::Constant)
+                        }
+                        _ => None,
+                    }
+                    .unwrap_or_else(|| {
+                        binary(Op::Sub, (**right).clone(), ArithmeticExpr::Constant(1))
+                    });
+                    binary(
+                        Op::Mul,
+                        binary(
+                            Op::Mul,
+                            (**right).clone(),
+                            binary(Op::Pow, (**left).clone(), reduced),
+                        ),
+                        left.derivative(var),
+                    )
+                }
+                Op::Div | Op::Mod => panic!("`{}` has no derivative", op.symbol()),
+            },
+            ArithmeticExpr::Unary { func, arg } => match func {
+                UnaryFunc::Neg => ArithmeticExpr::Unary {
+                    func: UnaryFunc::Neg,
+                    arg: Box::new(arg.derivative(var)),
+                },
+                UnaryFunc::Abs => binary(
+                    Op::Mul,
+                    ArithmeticExpr::Unary {
+                        func: UnaryFunc::Sgn,
+                        arg: arg.clone(),
+                    },
+                    arg.derivative(var),
+                ),
+                UnaryFunc::Sgn => ArithmeticExpr::Constant(0),
+            },
+        }
+    }
+
+    /// Whether the evaluation of the expression may fail even when all
+    /// variables are bound: every operation may overflow, and so may `abs` and
+    /// `neg` of `i32::MIN`.
+    fn may_fail(&self) -> bool {
+        match self {
+            ArithmeticExpr::Constant(_) | ArithmeticExpr::Variable(_) => false,
+            ArithmeticExpr::Operation { .. } => true,
+            ArithmeticExpr::Unary {
+                func: UnaryFunc::Sgn,
+                arg,
+            } => arg.may_fail(),
+            ArithmeticExpr::Unary { .. } => true,
+        }
+    }
+
+    /// Expand the expression into a sum of products.
+    ///
+    /// Each term is a coefficient and the sorted multiset of variables it
+    /// multiplies, constants having no variable. Like terms are combined, terms
+    /// with a zero coefficient are dropped, and terms are sorted by variables.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the expression uses `abs`, `sgn`, `/` or `%`, which are not
+    /// polynomial, or `^` with an exponent that is not a nonnegative constant.
+    /// Also panics if a coefficient overflows an `i32` during the expansion.
+    pub fn to_sop(&self) -> Vec<(i32, Vec<usize>)> {
+        self.sop_terms()
+            .into_iter()
+            .map(|(vars, coef)| (coef, vars))
+            .collect()
+    }
+
+    fn sop_terms(&self) -> BTreeMap<Vec<usize>, i32> {
+        let mut terms = BTreeMap::new();
+        match self {
+            ArithmeticExpr::Constant(c) => {
+                terms.insert(vec![], *c);
+            }
+            ArithmeticExpr::Variable(i) => {
+                terms.insert(vec![*i], 1);
+            }
+            ArithmeticExpr::Operation { op, left, right } => {
+                let (left, right) = (left.sop_terms(), right.sop_terms());
+                match op {
+                    Op::Add | Op::Sub => {
+                        terms = left;
+                        for (vars, coef) in right {
+                            let sum = terms.entry(vars).or_insert(0);
+                            *sum = match op {
+                                Op::Add => sum.checked_add(coef),
+                                _ => sum.checked_sub(coef),
+                            }
+                            .expect(SOP_OVERFLOW);
+                        }
+                    }
+                    Op::Div | Op::Mod => {
+                        panic!("`{}` has no sum of products form", op.symbol())
+                    }
+                    Op::Mul => terms = sop_product(&left, &right),
+                    Op::Pow => {
+                        let exp = match (right.len(), right.get(&vec![])) {
+                            (0, _) => 0,
+                            (1, Some(&exp)) if exp >= 0 => exp,
+                            _ => panic!("`^` has no sum of products form for this exponent"),
+                        };
+                        terms.insert(vec![], 1);
+                        for _ in 0..exp {
+                            terms = sop_product(&terms, &left);
+                        }
+                    }
+                }
+            }
+            ArithmeticExpr::Unary { func, arg } => match func {
+                UnaryFunc::Neg => {
+                    terms = arg.sop_terms();
+                    terms
+                        .values_mut()
+                        .for_each(|coef| *coef = coef.checked_neg().expect(SOP_OVERFLOW));
+                }
+                UnaryFunc::Abs | UnaryFunc::Sgn => {
+                    panic!("`{}` has no sum of products form", func.name())
+                }
+            },
+        }
+        terms.retain(|_, coef| *coef != 0);
+        terms
+    }
+
+    /// Serialize the expression to Reverse Polish Notation, as read by
+    /// [`ArithmeticExpr::from_rpn`].
+    pub fn to_rpn(&self) -> String {
+        match self {
+            ArithmeticExpr::Constant(c) => c.to_string(),
+            ArithmeticExpr::Variable(i) => format!("x_{i}"),
+            ArithmeticExpr::Operation { op, left, right } => {
+                format!("{} {} {}", left.to_rpn(), right.to_rpn(), op.symbol())
+            }
+            ArithmeticExpr::Unary { func, arg } => format!("{} {}", arg.to_rpn(), func.name()),
+        }
+    }
+
+    /// Binding power of the outermost construct of the expression in infix
+    /// notation: a negative constant reads as a negation.
+    fn infix_precedence(&self) -> u8 {
+        match self {
+            ArithmeticExpr::Constant(c) if *c < 0 => UNARY_PRECEDENCE,
+            ArithmeticExpr::Operation { op, .. } => op.precedence(),
+            ArithmeticExpr::Unary {
+                func: UnaryFunc::Neg,
+                ..
+            } => UNARY_PRECEDENCE,
+            _ => u8::MAX,
+        }
     }
 
-    pub fn evaluate(&self, vars: &HashMap<usize, i32>) -> i32 {
-        todo!("Compute the value of the expression using the given values for free variables")
+    /// Write the expression in infix notation, in parentheses if it would
+    /// otherwise not bind at least as tightly as `min_precedence`.
+    fn fmt_operand(&self, f: &mut fmt::Formatter<'_>, min_precedence: u8) -> fmt::Result {
+        if self.infix_precedence() < min_precedence {
+            write!(f, "({self})")
+        } else {
+            write!(f, "{self}")
+        }
+    }
+
+    /// Lower the expression to three-address code, in evaluation order.
+    ///
+    /// Each instruction stores its result in a new temporary, numbered from
+    /// `t0`, so the last instruction holds the value of the expression.
+    pub fn to_tac(&self) -> Vec<TacInstr> {
+        let mut code = Vec::new();
+        if let src @ (Operand::Constant(_) | Operand::Variable(_)) = self.lower(&mut code) {
+            code.push(TacInstr::Copy { dest: 0, src });
+        }
+        code
+    }
+
+    /// Append the code computing `self` to `code`, returning the operand holding its value.
+    fn lower(&self, code: &mut Vec<TacInstr>) -> Operand {
+        let instr = match self {
+            ArithmeticExpr::Constant(c) => return Operand::Constant(*c),
+            ArithmeticExpr::Variable(i) => return Operand::Variable(*i),
+            ArithmeticExpr::Operation { op, left, right } => {
+                let left = left.lower(code);
+                let right = right.lower(code);
+                TacInstr::Binary {
+                    dest: code.len(),
+                    op: *op,
+                    left,
+                    right,
+                }
+            }
+            ArithmeticExpr::Unary { func, arg } => {
+                let arg = arg.lower(code);
+                TacInstr::Unary {
+                    dest: code.len(),
+                    func: *func,
+                    arg,
+                }
+            }
+        };
+        code.push(instr);
+        Operand::Temp(code.len() - 1)
+    }
+
+    /// Render the expression tree in the Graphviz DOT format.
+    ///
+    /// Nodes are named `n0`, `n1`, ... in pre-order, and edges point from
+    /// each operator to its operands.
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph {\n");
+        self.write_dot(&mut dot, &mut 0);
+        dot.push_str("}\n");
+        dot
+    }
+
+    /// Write the nodes and edges of `self` to `dot`, returning the id of its root.
+    fn write_dot(&self, dot: &mut String, next_id: &mut usize) -> usize {
+        let id = *next_id;
+        *next_id += 1;
+        let label = match self {
+            ArithmeticExpr::Constant(c) => c.to_string(),
+            ArithmeticExpr::Variable(i) => format!("x_{i}"),
+            ArithmeticExpr::Operation { op, .. } => op.symbol().to_string(),
+            ArithmeticExpr::Unary { func, .. } => func.name().to_string(),
+        };
+        dot.push_str(&format!("    n{id} [label=\"{label}\"];\n"));
+
+        let children: Vec<&ArithmeticExpr> = match self {
+            ArithmeticExpr::Constant(_) | ArithmeticExpr::Variable(_) => vec![],
+            ArithmeticExpr::Operation { left, right, .. } => vec![left, right],
+            ArithmeticExpr::Unary { arg, .. } => vec![arg],
+        };
+        for child in children {
+            let child_id = child.write_dot(dot, next_id);
+            dot.push_str(&format!("    n{id} -> n{child_id};\n"));
+        }
+        id
+    }
+
+    /// Find the subexpressions other than constants and variables that occur
+    /// more than once, with their number of occurrences.
+    ///
+    /// Subexpressions are compared structurally, so `x_1 + x_2` and `x_2 + x_1`
+    /// are different. They are listed in order of first occurrence in pre-order.
+    pub fn common_subexprs(&self) -> Vec<(ArithmeticExpr, usize)> {
+        let mut counts = Vec::new();
+        let mut index = HashMap::new();
+        self.count_subexprs(&mut counts, &mut index);
+        counts
+            .into_iter()
+            .filter(|(_, count)| *count > 1)
+            .map(|(expr, count)| (expr.clone(), count))
+            .collect()
+    }
+
+    fn count_subexprs<'a>(
+        &'a self,
+        counts: &mut Vec<(&'a ArithmeticExpr, usize)>,
+        index: &mut HashMap<&'a ArithmeticExpr, usize>,
+    ) {
+        let children: Vec<&ArithmeticExpr> = match self {
+            ArithmeticExpr::Constant(_) | ArithmeticExpr::Variable(_) => return,
+            ArithmeticExpr::Operation { left, right, .. } => vec![left, right],
+            ArithmeticExpr::Unary { arg, .. } => vec![arg],
+        };
+        let i = *index.entry(self).or_insert_with(|| {
+            counts.push((self, 0));
+            counts.len() - 1
+        });
+        counts[i].1 += 1;
+        for child in children {
+            child.count_subexprs(counts, index);
+        }
+    }
+
+    /// Whether the expression is affine in `x_var`, i.e. of the form `a * x_var + b`
+    /// where `a` and `b` do not depend on `x_var`, other variables being
+    /// treated as constants.
+    ///
+    /// The check is syntactic: `x_1 * x_1 - x_1 * x_1` is not affine in `x_1`.
+    pub fn is_affine_in(&self, var: usize) -> bool {
+        self.degree_in(var).is_some_and(|d| d <= 1)
+    }
+
+    /// The degree of the expression as a polynomial in `x_var`, or `None`
+    /// if `x_var` appears under `abs`, `sgn`, `/` or `%`, or in a power whose
+    /// exponent is not a nonnegative constant.
+    fn degree_in(&self, var: usize) -> Option<u32> {
+        match self {
+            ArithmeticExpr::Constant(_) => Some(0),
+            ArithmeticExpr::Variable(i) => Some(u32::from(*i == var)),
+            ArithmeticExpr::Operation { op, left, right } => {
+                let (l, r) = (left.degree_in(var)?, right.degree_in(var)?);
+                match op {
+                    Op::Add | Op::Sub => Some(l.max(r)),
+                    Op::Mul => Some(l + r),
+                    Op::Div | Op::Mod => (l == 0 && r == 0).then_some(0),
+                    Op::Pow => match **right {
+                        ArithmeticExpr::Constant(exp) if exp >= 0 => Some(l * exp as u32),
+                        _ => (l == 0 && r == 0).then_some(0),
+                    },
+                }
+            }
+            ArithmeticExpr::Unary { func, arg } => match (func, arg.degree_in(var)?) {
+                (UnaryFunc::Neg, d) => Some(d),
+                (UnaryFunc::Abs | UnaryFunc::Sgn, 0) => Some(0),
+                (UnaryFunc::Abs | UnaryFunc::Sgn, _) => None,
+            },
+        }
+    }
+
+    /// The largest variable index used in the expression, if any.
+    pub fn max_var_index(&self) -> Option<usize> {
+        match self {
+            ArithmeticExpr::Constant(_) => None,
+            ArithmeticExpr::Variable(i) => Some(*i),
+            ArithmeticExpr::Operation { left, right, .. } => {
+                left.max_var_index().max(right.max_var_index())
+            }
+            ArithmeticExpr::Unary { arg, .. } => arg.max_var_index(),
+        }
+    }
+
+    /// Count the multiplications of the expression.
+    ///
+    /// A power `a ^ k` with a constant exponent `k >= 1` counts as the `k - 1`
+    /// multiplications of its expansion `a * a * ... * a`, and `a ^ 0` as none.
+    /// A power with any other exponent counts as a single multiplication.
+    pub fn num_multiplications(&self) -> usize {
+        match self {
+            ArithmeticExpr::Constant(_) | ArithmeticExpr::Variable(_) => 0,
+            ArithmeticExpr::Operation { op, left, right } => {
+                let own = match (op, &**right) {
+                    (Op::Mul, _) => 1,
+                    (Op::Pow, ArithmeticExpr::Constant(k)) if *k >= 0 => {
+                        usize::try_from(*k).unwrap().saturating_sub(1)
+                    }
+                    (Op::Pow, _) => 1,
+                    _ => 0,
+                };
+                own + left.num_multiplications() + right.num_multiplications()
+            }
+            ArithmeticExpr::Unary { arg, .. } => arg.num_multiplications(),
+        }
+    }
+
+    /// Sum the cost of every node of the expression, constants and variables costing 1.
+    pub fn cost(&self, weights: &OpCosts) -> u64 {
+        match self {
+            ArithmeticExpr::Constant(_) | ArithmeticExpr::Variable(_) => 1,
+            ArithmeticExpr::Operation { op, left, right } => {
+                weights.of(*op) + left.cost(weights) + right.cost(weights)
+            }
+            ArithmeticExpr::Unary { arg, .. } => weights.unary + arg.cost(weights),
+        }
+    }
+
+    /// Evaluate the expression, `x_i` taking the value `vars[&i]`.
+    pub fn evaluate(&self, vars: &HashMap<usize, i32>) -> Result<i32, EvalError> {
+        match self {
+            ArithmeticExpr::Constant(c) => Ok(*c),
+            ArithmeticExpr::Variable(i) => {
+                vars.get(i).copied().ok_or(EvalError::UndefinedVariable(*i))
+            }
+            ArithmeticExpr::Operation { op, left, right } => {
+                op.apply(left.evaluate(vars)?, right.evaluate(vars)?)
+            }
+            ArithmeticExpr::Unary { func, arg } => func.apply(arg.evaluate(vars)?),
+        }
+    }
+
+    /// Evaluate the expression on each of the first `rows` rows of `columns`,
+    /// where column `i` holds the successive values of `x_i`.
+    pub fn evaluate_columns(
+        &self,
+        columns: &HashMap<usize, Vec<i32>>,
+        rows: usize,
+    ) -> Result<Vec<i32>, EvalError> {
+        let vars = self.variables();
+        for &i in &vars {
+            let column = columns.get(&i).ok_or(EvalError::UndefinedVariable(i))?;
+            if column.len() < rows {
+                return Err(EvalError::ShortColumn {
+                    var: i,
+                    len: column.len(),
+                });
+            }
+        }
+
+        (0..rows)
+            .map(|row| {
+                let values = vars.iter().map(|&i| (i, columns[&i][row])).collect();
+                self.evaluate(&values)
+            })
+            .collect()
+    }
+
+    /// The indices of the variables used in the expression.
+    fn variables(&self) -> BTreeSet<usize> {
+        match self {
+            ArithmeticExpr::Constant(_) => BTreeSet::new(),
+            ArithmeticExpr::Variable(i) => BTreeSet::from([*i]),
+            ArithmeticExpr::Operation { left, right, .. } => {
+                let mut vars = left.variables();
+                vars.extend(right.variables());
+                vars
+            }
+            ArithmeticExpr::Unary { arg, .. } => arg.variables(),
+        }
+    }
+
+    /// Whether the expression evaluates to a nonzero value, as a C condition would.
+    pub fn evaluate_nonzero(&self, vars: &HashMap<usize, i32>) -> Result<bool, EvalError> {
+        Ok(self.evaluate(vars)? != 0)
+    }
+
+    /// Same as [`ArithmeticExpr::evaluate`], applying each operator only once
+    /// per distinct subexpression.
+    ///
+    /// `cache` holds the values of the subexpressions already evaluated. It can
+    /// be kept across calls with the same `vars`, but must be cleared when they
+    /// change.
+    pub fn evaluate_cached(
+        &self,
+        vars: &HashMap<usize, i32>,
+        cache: &mut EvalCache,
+    ) -> Result<i32, EvalError> {
+        let id = self.evaluate_interned(vars, cache)?;
+        Ok(cache.values[id])
+    }
+
+    /// Evaluate the expression into `cache`, bottom-up, returning its number there.
+    fn evaluate_interned(
+        &self,
+        vars: &HashMap<usize, i32>,
+        cache: &mut EvalCache,
+    ) -> Result<usize, EvalError> {
+        let key = match self {
+            ArithmeticExpr::Constant(c) => CacheKey::Constant(*c),
+            ArithmeticExpr::Variable(i) => CacheKey::Variable(*i),
+            ArithmeticExpr::Operation { op, left, right } => CacheKey::Operation(
+                *op,
+                left.evaluate_interned(vars, cache)?,
+                right.evaluate_interned(vars, cache)?,
+            ),
+            ArithmeticExpr::Unary { func, arg } => {
+                CacheKey::Unary(*func, arg.evaluate_interned(vars, cache)?)
+            }
+        };
+        if let Some(&id) = cache.ids.get(&key) {
+            return Ok(id);
+        }
+        let value = match key {
+            CacheKey::Constant(c) => c,
+            CacheKey::Variable(i) => vars
+                .get(&i)
+                .copied()
+                .ok_or(EvalError::UndefinedVariable(i))?,
+            CacheKey::Operation(op, l, r) => op.apply(cache.values[l], cache.values[r])?,
+            CacheKey::Unary(func, arg) => func.apply(cache.values[arg])?,
+        };
+        let id = cache.values.len();
+        cache.values.push(value);
+        cache.ids.insert(key, id);
+        Ok(id)
+    }
+
+    /// Same as [`ArithmeticExpr::evaluate`], evaluating both operands of large
+    /// operations in parallel.
+    #[cfg(feature = "rayon")]
+    pub fn evaluate_parallel(&self, vars: &HashMap<usize, i32>) -> Result<i32, EvalError> {
+        match self {
+            ArithmeticExpr::Operation { op, left, right }
+                if self.size_up_to(PARALLEL_THRESHOLD) >= PARALLEL_THRESHOLD =>
+            {
+                let (l, r) = rayon::join(
+                    || left.evaluate_parallel(vars),
+                    || right.evaluate_parallel(vars),
+                );
+                op.apply(l?, r?)
+            }
+            ArithmeticExpr::Unary { func, arg } => func.apply(arg.evaluate_parallel(vars)?),
+            _ => self.evaluate(vars),
+        }
+    }
+
+    /// Same as [`ArithmeticExpr::size`], but stops counting once `limit` is reached.
+    #[cfg(feature = "rayon")]
+    fn size_up_to(&self, limit: usize) -> usize {
+        match self {
+            ArithmeticExpr::Constant(_) | ArithmeticExpr::Variable(_) => 1,
+            ArithmeticExpr::Operation { left, right, .. } => {
+                let l = left.size_up_to(limit.saturating_sub(1));
+                if 1 + l >= limit {
+                    return 1 + l;
+                }
+                1 + l + right.size_up_to(limit - 1 - l)
+            }
+            ArithmeticExpr::Unary { arg, .. } => 1 + arg.size_up_to(limit.saturating_sub(1)),
+        }
+    }
+
+    /// Evaluate the expression in floating point, constants being converted to `f64`.
+    ///
+    /// Only a missing variable is an error: divisions by zero and overflows
+    /// follow the IEEE 754 rules.
+    pub fn evaluate_f64(&self, vars: &HashMap<usize, f64>) -> Result<f64, EvalError> {
+        match self {
+            ArithmeticExpr::Constant(c) => Ok(f64::from(*c)),
+            ArithmeticExpr::Variable(i) => {
+                vars.get(i).copied().ok_or(EvalError::UndefinedVariable(*i))
+            }
+            ArithmeticExpr::Operation { op, left, right } => {
+                Ok(op.apply_f64(left.evaluate_f64(vars)?, right.evaluate_f64(vars)?))
+            }
+            ArithmeticExpr::Unary { func, arg } => Ok(func.apply_f64(arg.evaluate_f64(vars)?)),
+        }
+    }
+
+    /// Whether `self` and `other` evaluate within `eps` of each other, in floating
+    /// point, on every assignment of `vars_samples`.
+    ///
+    /// An assignment on which both fail to evaluate with the same error counts
+    /// as a match, one on which only one of them fails as a mismatch.
+    pub fn approx_eq(
+        &self,
+        other: &ArithmeticExpr,
+        vars_samples: &[HashMap<usize, f64>],
+        eps: f64,
+    ) -> bool {
+        vars_samples.iter().all(
+            |vars| match (self.evaluate_f64(vars), other.evaluate_f64(vars)) {
+                (Ok(a), Ok(b)) => (a - b).abs() <= eps,
+                (a, b) => a == b,
+            },
+        )
+    }
+}
+
+/// Split `s` into the tokens of [`ArithmeticExpr::parse`], each with its text.
+///
+/// Words, made of ASCII letters, digits and `_`, are read as a whole, so
+/// `3x_1` is a single invalid token rather than `3` followed by `x_1`.
+fn tokenize(s: &str) -> Result<Vec<(Token, &str)>, ParseError> {
+    let is_word_char = |c: char| c.is_ascii_alphanumeric() || c == '_';
+    let mut tokens = Vec::new();
+    let mut rest = s.trim_start();
+    while let Some(c) = rest.chars().next() {
+        let len = if is_word_char(c) {
+            rest.find(|c| !is_word_char(c)).unwrap_or(rest.len())
+        } else {
+            c.len_utf8()
+        };
+        let (text, tail) = rest.split_at(len);
+        let token = if text == "(" {
+            Token::Open
+        } else if text == ")" {
+            Token::Close
+        } else if let Some(op) = Op::from_token(text) {
+            Token::Op(op)
+        } else if let Some(func) = UnaryFunc::from_token(text) {
+            Token::Func(func)
+        } else if let Some(index) = text.strip_prefix("x_") {
+            let index = index
+                .parse()
+                .map_err(|_| ParseError::InvalidVariable(text.to_string()))?;
+            Token::Variable(index)
+        } else {
+            let c = text
+                .parse()
+                .map_err(|_| ParseError::UnexpectedToken(text.to_string()))?;
+            Token::Constant(c)
+        };
+        tokens.push((token, text));
+        rest = tail.trim_start();
+    }
+    Ok(tokens)
+}
+
+/// The operation `left op right`.
+fn binary(op: Op, left: ArithmeticExpr, right: ArithmeticExpr) -> ArithmeticExpr {
+    ArithmeticExpr::Operation {
+        op,
+        left: Box::new(left),
+        right: Box::new(right),
+    }
+}
+
+/// Panic message of [`ArithmeticExpr::to_sop`] when a coefficient overflows.
+const SOP_OVERFLOW: &str = "overflow in a coefficient of the sum of products";
+
+/// Multiply two sums of products, see [`ArithmeticExpr::to_sop`].
+fn sop_product(
+    left: &BTreeMap<Vec<usize>, i32>,
+    right: &BTreeMap<Vec<usize>, i32>,
+) -> BTreeMap<Vec<usize>, i32> {
+    let mut terms = BTreeMap::new();
+    for (lvars, lcoef) in left {
+        for (rvars, rcoef) in right {
+            let mut vars = [lvars.as_slice(), rvars.as_slice()].concat();
+            vars.sort_unstable();
+            let sum: &mut i32 = terms.entry(vars).or_insert(0);
+            *sum = lcoef
+                .checked_mul(*rcoef)
+                .and_then(|product| sum.checked_add(product))
+                .expect(SOP_OVERFLOW);
+        }
+    }
+    terms
+}
+
+/// Generate a random well-formed expression of depth at most `max_depth`,
+/// using constants in `-3..=3` and variables `x_0` to `x_{n_vars - 1}`.
+///
+/// Meant for property tests, available in tests and with the `rand` feature.
+#[cfg(any(test, feature = "rand"))]
+pub fn random_expr(rng: &mut impl rand::Rng, max_depth: usize, n_vars: usize) -> ArithmeticExpr {
+    const OPS: [Op; 6] = [Op::Add, Op::Sub, Op::Mul, Op::Div, Op::Mod, Op::Pow];
+    const FUNCS: [UnaryFunc; 3] = [UnaryFunc::Abs, UnaryFunc::Neg, UnaryFunc::Sgn];
+
+    match rng.gen_range(0..if max_depth == 0 { 2 } else { 6 }) {
+        0 if n_vars > 0 => ArithmeticExpr::Variable(rng.gen_range(0..n_vars)),
+        0 | 1 => ArithmeticExpr::Constant(rng.gen_range(-3..=3)),
+        2 => ArithmeticExpr::Unary {
+            func: FUNCS[rng.gen_range(0..FUNCS.len())],
+            arg: Box::new(random_expr(rng, max_depth - 1, n_vars)),
+        },
+        _ => ArithmeticExpr::Operation {
+            op: OPS[rng.gen_range(0..OPS.len())],
+            left: Box::new(random_expr(rng, max_depth - 1, n_vars)),
+            right: Box::new(random_expr(rng, max_depth - 1, n_vars)),
+        },
     }
 }
 
@@ -25,65 +1344,973 @@ mod test {
     #[test]
     fn simple_add() {
         let rpn = "3 4 +";
-        let expr = ArithmeticExpr::from_rpn(rpn);
+        let expr = ArithmeticExpr::from_rpn(rpn).unwrap();
 
         assert_eq!(expr.size(), 3);
-        assert_eq!(expr.evaluate(&HashMap::default()), 7);
+        assert_eq!(expr.evaluate(&HashMap::default()).unwrap(), 7);
     }
 
     #[test]
     fn mul_var() {
         let rpn = "3 x_1 *";
-        let expr = ArithmeticExpr::from_rpn(rpn);
+        let expr = ArithmeticExpr::from_rpn(rpn).unwrap();
 
         assert_eq!(expr.size(), 3);
         let mut vars = HashMap::default();
         vars.insert(1, 3);
-        assert_eq!(expr.evaluate(&vars), 9);
+        assert_eq!(expr.evaluate(&vars).unwrap(), 9);
 
         vars.insert(1, 5);
-        assert_eq!(expr.evaluate(&vars), 15);
+        assert_eq!(expr.evaluate(&vars).unwrap(), 15);
     }
 
     #[test]
     fn sub() {
         let rpn = "7 9 -";
-        let expr = ArithmeticExpr::from_rpn(rpn);
+        let expr = ArithmeticExpr::from_rpn(rpn).unwrap();
 
-        assert_eq!(expr.evaluate(&HashMap::default()), -2);
+        assert_eq!(expr.evaluate(&HashMap::default()).unwrap(), -2);
     }
 
     #[test]
     fn depth_two() {
         let rpn = "7 9 - 4 +";
-        let expr = ArithmeticExpr::from_rpn(rpn);
+        let expr = ArithmeticExpr::from_rpn(rpn).unwrap();
 
-        assert_eq!(expr.evaluate(&HashMap::default()), 2);
+        assert_eq!(expr.evaluate(&HashMap::default()).unwrap(), 2);
     }
 
     #[test]
     fn odd_sum() {
         let rpn = "1 3 + 5 + 7 9 + 11 + +";
-        let expr = ArithmeticExpr::from_rpn(rpn);
+        let expr = ArithmeticExpr::from_rpn(rpn).unwrap();
 
         assert_eq!(expr.size(), 11);
-        assert_eq!(expr.evaluate(&HashMap::default()), 36);
+        assert_eq!(expr.evaluate(&HashMap::default()).unwrap(), 36);
     }
 
     #[test]
     fn many_variables() {
         let rpn = "x_1 1 + x_2 + x_3 2 * *";
-        let expr = ArithmeticExpr::from_rpn(rpn);
+        let expr = ArithmeticExpr::from_rpn(rpn).unwrap();
 
         let mut vars = HashMap::default();
         vars.insert(1, 8);
         vars.insert(2, 2);
         vars.insert(3, 5);
-        assert_eq!(expr.evaluate(&vars), 110);
+        assert_eq!(expr.evaluate(&vars).unwrap(), 110);
 
         vars.insert(1, 984);
         vars.insert(2, 17);
         vars.insert(3, 0);
-        assert_eq!(expr.evaluate(&vars), 0);
+        assert_eq!(expr.evaluate(&vars).unwrap(), 0);
+    }
+
+    #[test]
+    fn unary_functions() {
+        let expr = ArithmeticExpr::from_rpn("5 neg abs").unwrap();
+        assert_eq!(expr.size(), 3);
+        assert_eq!(expr.evaluate(&HashMap::default()).unwrap(), 5);
+
+        let expr = ArithmeticExpr::from_rpn("3 sgn").unwrap();
+        assert_eq!(expr.evaluate(&HashMap::default()).unwrap(), 1);
+
+        let expr = ArithmeticExpr::from_rpn("2 x_1 - sgn").unwrap();
+        let mut vars = HashMap::default();
+        vars.insert(1, 7);
+        assert_eq!(expr.evaluate(&vars).unwrap(), -1);
+    }
+
+    #[test]
+    fn negation() {
+        let expr = ArithmeticExpr::from_rpn("5 neg").unwrap();
+        assert_eq!(expr.size(), 2);
+        assert_eq!(expr.evaluate(&HashMap::default()).unwrap(), -5);
+
+        let expr = ArithmeticExpr::from_rpn("x_1 neg").unwrap();
+        assert_eq!(
+            expr,
+            ArithmeticExpr::Unary {
+                func: UnaryFunc::Neg,
+                arg: Box::new(ArithmeticExpr::Variable(1)),
+            }
+        );
+        assert_eq!(expr.evaluate(&HashMap::from([(1, 12)])).unwrap(), -12);
+        assert_eq!(expr.evaluate(&HashMap::from([(1, -3)])).unwrap(), 3);
+    }
+
+    #[test]
+    fn approx_eq_simplified() {
+        let original = ArithmeticExpr::from_rpn("x_1 1 * 0 + x_2 2 * x_2 - +").unwrap();
+        let simplified = ArithmeticExpr::from_rpn("x_1 x_2 +").unwrap();
+        let wrong = ArithmeticExpr::from_rpn("x_1 x_2 *").unwrap();
+
+        // Deterministic pseudo-random samples in [-50, 50).
+        let mut seed = 0x2545_f491_u64;
+        let mut next = || {
+            seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1);
+            (seed >> 11) as f64 / (1u64 << 53) as f64 * 100.0 - 50.0
+        };
+        let samples: Vec<HashMap<usize, f64>> = (0..100)
+            .map(|_| HashMap::from([(1, next()), (2, next())]))
+            .collect();
+
+        assert!(original.approx_eq(&simplified, &samples, 1e-9));
+        assert!(!original.approx_eq(&wrong, &samples, 1e-9));
+
+        let missing = [HashMap::from([(1, 2.0)])];
+        assert_eq!(
+            original.evaluate_f64(&missing[0]),
+            Err(EvalError::UndefinedVariable(2))
+        );
+        assert!(original.approx_eq(&wrong, &missing, 1e-9));
+        assert!(!original.approx_eq(&ArithmeticExpr::Variable(1), &missing, 1e-9));
+    }
+
+    #[test]
+    fn cost_of_equivalent_forms() {
+        let weights = OpCosts {
+            add: 1,
+            sub: 1,
+            mul: 5,
+            div: 8,
+            pow: 10,
+            unary: 2,
+        };
+        let expanded = ArithmeticExpr::from_rpn("x_1 x_2 * x_1 x_3 * +").unwrap();
+        let factored = ArithmeticExpr::from_rpn("x_1 x_2 x_3 + *").unwrap();
+
+        assert_eq!(expanded.cost(&weights), 15);
+        assert_eq!(factored.cost(&weights), 9);
+        assert_eq!(ArithmeticExpr::from_rpn("3 neg").unwrap().cost(&weights), 3);
+        assert_eq!(
+            ArithmeticExpr::from_rpn("x_1 2 /").unwrap().cost(&weights),
+            10
+        );
+    }
+
+    #[test]
+    fn dot_output() {
+        let dot = ArithmeticExpr::from_rpn("3 4 +").unwrap().to_dot();
+        assert!(dot.starts_with("digraph {"));
+        assert!(dot.contains("n0 [label=\"+\"];"));
+        assert!(dot.contains("n1 [label=\"3\"];"));
+        assert!(dot.contains("n2 [label=\"4\"];"));
+        assert!(dot.contains("n0 -> n1;"));
+        assert!(dot.contains("n0 -> n2;"));
+
+        let dot = ArithmeticExpr::from_rpn("x_2 neg").unwrap().to_dot();
+        assert!(dot.contains("n0 [label=\"neg\"];"));
+        assert!(dot.contains("n1 [label=\"x_2\"];"));
+        assert!(dot.contains("n0 -> n1;"));
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn parallel_matches_sequential() {
+        // Balanced expression with 2^15 leaves, alternating additions and subtractions.
+        fn balanced_rpn(depth: u32, index: &mut usize, rpn: &mut String) {
+            if depth == 0 {
+                let token = if (*index).is_multiple_of(3) {
+                    format!("x_{} ", *index % 5)
+                } else {
+                    format!("{} ", *index % 7)
+                };
+                rpn.push_str(&token);
+                *index += 1;
+                return;
+            }
+            balanced_rpn(depth - 1, index, rpn);
+            balanced_rpn(depth - 1, index, rpn);
+            rpn.push_str(if depth.is_multiple_of(2) { "+ " } else { "- " });
+        }
+
+        let mut rpn = String::new();
+        balanced_rpn(15, &mut 0, &mut rpn);
+        let expr = ArithmeticExpr::from_rpn(&rpn).unwrap();
+        let vars = (0..5).map(|i| (i, 3 * i as i32 - 4)).collect();
+
+        assert_eq!(
+            expr.evaluate_parallel(&vars).unwrap(),
+            expr.evaluate(&vars).unwrap()
+        );
+        let small = ArithmeticExpr::from_rpn("x_1 2 * 3 -").unwrap();
+        assert_eq!(
+            small.evaluate_parallel(&vars).unwrap(),
+            small.evaluate(&vars).unwrap()
+        );
+    }
+
+    #[test]
+    fn sum_of_products() {
+        let expr = ArithmeticExpr::from_rpn("x_1 1 + x_2 *").unwrap();
+        assert_eq!(expr.to_sop(), vec![(1, vec![1, 2]), (1, vec![2])]);
+
+        let expr = ArithmeticExpr::from_rpn("x_1 2 + x_1 3 - *").unwrap();
+        assert_eq!(
+            expr.to_sop(),
+            vec![(-6, vec![]), (-1, vec![1]), (1, vec![1, 1])]
+        );
+
+        let expr = ArithmeticExpr::from_rpn("x_2 x_1 * x_1 x_2 * -").unwrap();
+        assert_eq!(expr.to_sop(), vec![]);
+
+        let expr = ArithmeticExpr::from_rpn("x_1 neg 4 *").unwrap();
+        assert_eq!(expr.to_sop(), vec![(-4, vec![1])]);
+    }
+
+    #[test]
+    fn count_multiplications() {
+        let expr = ArithmeticExpr::from_rpn("x_1 x_2 * x_3 *").unwrap();
+        assert_eq!(expr.num_multiplications(), 2);
+
+        let expr = ArithmeticExpr::from_rpn("x_1 2 * neg 3 + 4 -").unwrap();
+        assert_eq!(expr.num_multiplications(), 1);
+        assert_eq!(
+            ArithmeticExpr::from_rpn("7").unwrap().num_multiplications(),
+            0
+        );
+
+        for (infix, count) in [
+            ("x_1 ^ 3", 2),
+            ("x_1 ^ 1", 0),
+            ("x_1 ^ 0", 0),
+            ("(x_1 * x_2) ^ 4 + x_3", 4),
+            ("2 ^ x_1", 1),
+            ("x_1 ^ (2 * x_2)", 2),
+        ] {
+            let expr = ArithmeticExpr::parse(infix).unwrap();
+            assert_eq!(expr.num_multiplications(), count, "{infix}");
+        }
+    }
+
+    #[test]
+    fn common_subexpressions() {
+        let expr = ArithmeticExpr::from_rpn("x_1 x_2 + 3 * x_1 x_2 + -").unwrap();
+        assert_eq!(
+            expr.common_subexprs(),
+            vec![(ArithmeticExpr::from_rpn("x_1 x_2 +").unwrap(), 2)]
+        );
+
+        let expr = ArithmeticExpr::from_rpn("x_1 x_2 + x_2 x_1 + *").unwrap();
+        assert_eq!(expr.common_subexprs(), vec![]);
+    }
+
+    #[test]
+    fn cached_evaluation() {
+        let expr = ArithmeticExpr::from_rpn("x_1 x_2 + x_1 x_2 + * x_1 x_2 + -").unwrap();
+        let mut vars = HashMap::from([(1, 4), (2, -7)]);
+
+        let mut cache = EvalCache::new();
+        assert_eq!(
+            expr.evaluate_cached(&vars, &mut cache).unwrap(),
+            expr.evaluate(&vars).unwrap()
+        );
+        // x_1, x_2, their sum, the product and the difference.
+        assert_eq!(cache.len(), 5);
+        let sum = ArithmeticExpr::from_rpn("x_1 x_2 +").unwrap();
+        assert_eq!(cache.get(&sum), Some(-3));
+        assert_eq!(
+            cache.get(&ArithmeticExpr::from_rpn("x_1 x_2 -").unwrap()),
+            None
+        );
+
+        vars.insert(2, 5);
+        cache.clear();
+        assert_eq!(expr.evaluate_cached(&vars, &mut cache).unwrap(), 72);
+    }
+
+    #[test]
+    fn cached_evaluation_of_repeated_subtree() {
+        // A tree with 2^16 copies of x_1, whose 17 levels are each a single
+        // distinct subexpression.
+        let mut expr = ArithmeticExpr::Variable(1);
+        for _ in 0..16 {
+            expr = ArithmeticExpr::Operation {
+                op: Op::Add,
+                left: Box::new(expr.clone()),
+                right: Box::new(expr),
+            };
+        }
+        assert_eq!(expr.size(), (1 << 17) - 1);
+
+        let vars = HashMap::from([(1, 7)]);
+        let mut cache = EvalCache::new();
+        assert_eq!(expr.evaluate_cached(&vars, &mut cache), Ok(7 << 16));
+        assert_eq!(cache.len(), 17);
+        assert_eq!(expr.evaluate_cached(&vars, &mut cache), Ok(7 << 16));
+        assert_eq!(cache.len(), 17);
+    }
+
+    #[test]
+    fn max_variable_index() {
+        assert_eq!(
+            ArithmeticExpr::from_rpn("x_1 x_3 +")
+                .unwrap()
+                .max_var_index(),
+            Some(3)
+        );
+        assert_eq!(
+            ArithmeticExpr::from_rpn("x_0 neg").unwrap().max_var_index(),
+            Some(0)
+        );
+        assert_eq!(
+            ArithmeticExpr::from_rpn("1 2 * 3 +")
+                .unwrap()
+                .max_var_index(),
+            None
+        );
+    }
+
+    #[test]
+    fn random_rpn_roundtrip() {
+        use rand::{rngs::StdRng, Rng, SeedableRng};
+
+        let mut rng = StdRng::seed_from_u64(235);
+        for _ in 0..1000 {
+            let expr = random_expr(&mut rng, 4, 3);
+            let parsed = ArithmeticExpr::from_rpn(&expr.to_rpn()).unwrap();
+            assert_eq!(parsed, expr);
+
+            let vars = (0..3).map(|i| (i, rng.gen_range(-3..=3))).collect();
+            assert_eq!(parsed.evaluate(&vars), expr.evaluate(&vars));
+            assert!(expr.max_var_index().is_none_or(|i| i < 3));
+        }
+    }
+
+    #[test]
+    fn affine_in_variable() {
+        let expr = ArithmeticExpr::from_rpn("x_1 x_2 * x_1 +").unwrap();
+        assert!(expr.is_affine_in(1));
+        assert!(expr.is_affine_in(2));
+        assert!(expr.is_affine_in(3));
+
+        assert!(!ArithmeticExpr::from_rpn("x_1 x_1 *")
+            .unwrap()
+            .is_affine_in(1));
+        assert!(!ArithmeticExpr::from_rpn("x_1 abs").unwrap().is_affine_in(1));
+        assert!(ArithmeticExpr::from_rpn("x_2 abs x_1 * neg")
+            .unwrap()
+            .is_affine_in(1));
+    }
+
+    #[test]
+    fn nonzero_truthiness() {
+        let zero = ArithmeticExpr::from_rpn("x_1 x_1 -").unwrap();
+        let succ = ArithmeticExpr::from_rpn("x_1 1 +").unwrap();
+        for x in [-5, 3, 12] {
+            let vars = HashMap::from([(1, x)]);
+            assert!(!zero.evaluate_nonzero(&vars).unwrap());
+            assert!(succ.evaluate_nonzero(&vars).unwrap());
+        }
+        assert!(!succ.evaluate_nonzero(&HashMap::from([(1, -1)])).unwrap());
+    }
+
+    #[test]
+    fn add_neg_form() {
+        let expr = ArithmeticExpr::from_rpn("7 9 -").unwrap().to_add_neg_form();
+        assert_eq!(expr, ArithmeticExpr::from_rpn("7 9 neg +").unwrap());
+        assert_eq!(expr.evaluate(&HashMap::default()).unwrap(), -2);
+
+        let original = ArithmeticExpr::from_rpn("x_1 x_2 3 - * 4 x_1 - abs -").unwrap();
+        let vars = HashMap::from([(1, 5), (2, -2)]);
+        let rewritten = original.clone().to_add_neg_form();
+        assert_eq!(
+            rewritten.evaluate(&vars).unwrap(),
+            original.evaluate(&vars).unwrap()
+        );
+        assert!(!rewritten.to_rpn().split(' ').any(|token| token == "-"));
+    }
+
+    #[test]
+    fn evaluate_columns() {
+        let expr = ArithmeticExpr::from_rpn("x_1 x_2 +").unwrap();
+        let columns = HashMap::from([(1, vec![1, 2, 3, 4]), (2, vec![10, 20, 30, 40, 50])]);
+        assert_eq!(
+            expr.evaluate_columns(&columns, 4).unwrap(),
+            vec![11, 22, 33, 44]
+        );
+        assert_eq!(expr.evaluate_columns(&columns, 0).unwrap(), vec![]);
+
+        let constant = ArithmeticExpr::from_rpn("3 4 *").unwrap();
+        assert_eq!(
+            constant.evaluate_columns(&HashMap::new(), 2).unwrap(),
+            vec![12, 12]
+        );
+    }
+
+    #[test]
+    fn evaluate_short_column() {
+        let expr = ArithmeticExpr::from_rpn("x_1 x_2 +").unwrap();
+        let columns = HashMap::from([(1, vec![1, 2, 3, 4]), (2, vec![10, 20, 30, 40, 50])]);
+        let err = expr.evaluate_columns(&columns, 5).unwrap_err();
+        assert_eq!(err, EvalError::ShortColumn { var: 1, len: 4 });
+        assert_eq!(err.to_string(), "column for x_1 has only 4 rows");
+    }
+
+    #[test]
+    fn three_address_code() {
+        fn run(code: &[TacInstr], vars: &HashMap<usize, i32>) -> i32 {
+            let mut temps = vec![0; code.len()];
+            let value = |operand: Operand, temps: &[i32]| match operand {
+                Operand::Constant(c) => c,
+                Operand::Variable(i) => vars[&i],
+                Operand::Temp(t) => temps[t],
+            };
+            for instr in code {
+                match *instr {
+                    TacInstr::Copy { dest, src } => temps[dest] = value(src, &temps),
+                    TacInstr::Binary {
+                        dest,
+                        op,
+                        left,
+                        right,
+                    } => temps[dest] = op.apply(value(left, &temps), value(right, &temps)).unwrap(),
+                    TacInstr::Unary { dest, func, arg } => {
+                        temps[dest] = func.apply(value(arg, &temps)).unwrap()
+                    }
+                }
+            }
+            *temps.last().unwrap()
+        }
+
+        let expr = ArithmeticExpr::from_rpn("3 4 + x_1 *").unwrap();
+        let code = expr.to_tac();
+        let lines: Vec<String> = code.iter().map(ToString::to_string).collect();
+        assert_eq!(lines, ["t0 = 3 + 4", "t1 = t0 * x_1"]);
+        let vars = HashMap::from([(1, 6)]);
+        assert_eq!(run(&code, &vars), expr.evaluate(&vars).unwrap());
+
+        let expr = ArithmeticExpr::from_rpn("x_1 neg x_1 2 * -").unwrap();
+        let lines: Vec<String> = expr.to_tac().iter().map(ToString::to_string).collect();
+        assert_eq!(lines, ["t0 = neg x_1", "t1 = x_1 * 2", "t2 = t0 - t1"]);
+        assert_eq!(run(&expr.to_tac(), &vars), expr.evaluate(&vars).unwrap());
+
+        let lines: Vec<String> = ArithmeticExpr::from_rpn("x_3")
+            .unwrap()
+            .to_tac()
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(lines, ["t0 = x_3"]);
+    }
+
+    #[test]
+    fn flatten_chains() {
+        fn chain_len(expr: &ArithmeticExpr, op: Op) -> usize {
+            match expr {
+                ArithmeticExpr::Operation {
+                    op: node_op,
+                    left,
+                    right,
+                } if *node_op == op => {
+                    assert!(!matches!(**right, ArithmeticExpr::Operation { op: r, .. } if r == op));
+                    chain_len(left, op) + 1
+                }
+                _ => 1,
+            }
+        }
+
+        let expr = ArithmeticExpr::from_rpn("1 2 + 3 4 + +").unwrap().flatten();
+        assert_eq!(expr, ArithmeticExpr::from_rpn("1 2 + 3 + 4 +").unwrap());
+        assert_eq!(chain_len(&expr, Op::Add), 4);
+        assert_eq!(expr.evaluate(&HashMap::default()).unwrap(), 10);
+
+        let original = ArithmeticExpr::from_rpn("x_1 x_2 x_3 * * 5 x_1 x_2 + + -").unwrap();
+        let flat = original.clone().flatten();
+        assert_eq!(
+            flat,
+            ArithmeticExpr::from_rpn("x_1 x_2 * x_3 * 5 x_1 + x_2 + -").unwrap()
+        );
+        let vars = HashMap::from([(1, 2), (2, -3), (3, 4)]);
+        assert_eq!(
+            flat.evaluate(&vars).unwrap(),
+            original.evaluate(&vars).unwrap()
+        );
+    }
+
+    #[test]
+    fn division() {
+        let vars = HashMap::default();
+        assert_eq!(
+            ArithmeticExpr::from_rpn("6 2 /")
+                .unwrap()
+                .evaluate(&vars)
+                .unwrap(),
+            3
+        );
+        assert_eq!(
+            ArithmeticExpr::from_rpn("7 2 /")
+                .unwrap()
+                .evaluate(&vars)
+                .unwrap(),
+            3
+        );
+        assert_eq!(
+            ArithmeticExpr::from_rpn("7 neg 2 /")
+                .unwrap()
+                .evaluate(&vars)
+                .unwrap(),
+            -3
+        );
+
+        let expr = ArithmeticExpr::from_rpn("x_1 4 /").unwrap();
+        assert_eq!(expr.to_rpn(), "x_1 4 /");
+        assert_eq!(expr.evaluate(&HashMap::from([(1, 18)])).unwrap(), 4);
+        assert!(!expr.is_affine_in(1));
+        assert!(ArithmeticExpr::from_rpn("x_1 6 2 / *")
+            .unwrap()
+            .is_affine_in(1));
+    }
+
+    #[test]
+    fn division_by_zero() {
+        let vars = HashMap::from([(1, 5)]);
+        for rpn in ["1 x_1 x_1 - /", "1 x_1 x_1 - %"] {
+            let expr = ArithmeticExpr::from_rpn(rpn).unwrap();
+            assert_eq!(expr.evaluate(&vars), Err(EvalError::DivisionByZero));
+        }
+    }
+
+    #[test]
+    fn division_overflow() {
+        for rpn in ["-2147483648 -1 /", "-2147483648 -1 %"] {
+            let expr = ArithmeticExpr::from_rpn(rpn).unwrap();
+            assert_eq!(expr.evaluate(&HashMap::default()), Err(EvalError::Overflow));
+            assert_eq!(expr.clone().simplify(), expr);
+        }
+    }
+
+    #[test]
+    fn modulo() {
+        let vars = HashMap::default();
+        assert_eq!(
+            ArithmeticExpr::from_rpn("10 3 %")
+                .unwrap()
+                .evaluate(&vars)
+                .unwrap(),
+            1
+        );
+        assert_eq!(
+            ArithmeticExpr::from_rpn("7 neg 3 %")
+                .unwrap()
+                .evaluate(&vars)
+                .unwrap(),
+            -1
+        );
+        assert_eq!(
+            ArithmeticExpr::from_rpn("7 3 neg %")
+                .unwrap()
+                .evaluate(&vars)
+                .unwrap(),
+            1
+        );
+
+        let periodic = ArithmeticExpr::from_rpn("x_1 3 + 7 %").unwrap();
+        let values: Vec<i32> = (0..9)
+            .map(|x| periodic.evaluate(&HashMap::from([(1, x)])).unwrap())
+            .collect();
+        assert_eq!(values, [3, 4, 5, 6, 0, 1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn power() {
+        let expr = ArithmeticExpr::from_rpn("2 10 ^").unwrap();
+        assert_eq!(expr.evaluate(&HashMap::default()).unwrap(), 1024);
+
+        let square = ArithmeticExpr::from_rpn("x_1 2 ^").unwrap();
+        assert_eq!(square.evaluate(&HashMap::from([(1, -7)])).unwrap(), 49);
+        assert_eq!(square.to_sop(), [(1, vec![1, 1])]);
+        assert!(!square.is_affine_in(1));
+
+        let cube = ArithmeticExpr::from_rpn("x_1 1 + 3 ^").unwrap();
+        assert_eq!(
+            cube.to_sop(),
+            [
+                (1, vec![]),
+                (3, vec![1]),
+                (3, vec![1, 1]),
+                (1, vec![1, 1, 1])
+            ]
+        );
+        assert_eq!(
+            ArithmeticExpr::from_rpn("x_1 0 ^").unwrap().to_sop(),
+            [(1, vec![])]
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "overflow in a coefficient")]
+    fn sop_product_overflow() {
+        ArithmeticExpr::from_rpn("65536 x_1 * 65536 *")
+            .unwrap()
+            .to_sop();
+    }
+
+    #[test]
+    #[should_panic(expected = "overflow in a coefficient")]
+    fn sop_power_overflow() {
+        assert_eq!(
+            ArithmeticExpr::from_rpn("2 30 ^").unwrap().to_sop(),
+            [(1 << 30, vec![])]
+        );
+        ArithmeticExpr::from_rpn("2 31 ^").unwrap().to_sop();
+    }
+
+    #[test]
+    #[should_panic(expected = "no sum of products form")]
+    fn power_with_variable_exponent_has_no_sop() {
+        ArithmeticExpr::from_rpn("2 x_1 2 + ^").unwrap().to_sop();
+    }
+
+    #[test]
+    fn negative_exponent() {
+        let expr = ArithmeticExpr::from_rpn("2 1 neg ^").unwrap();
+        assert_eq!(
+            expr.evaluate(&HashMap::default()),
+            Err(EvalError::NegativeExponent(-1))
+        );
+    }
+
+    #[test]
+    fn power_overflow() {
+        let expr = ArithmeticExpr::from_rpn("10 10 ^").unwrap();
+        assert_eq!(expr.evaluate(&HashMap::default()), Err(EvalError::Overflow));
+    }
+
+    #[test]
+    fn rpn_parse_errors() {
+        use RpnParseError::*;
+
+        assert_eq!(ArithmeticExpr::from_rpn(""), Err(EmptyExpression));
+        assert_eq!(ArithmeticExpr::from_rpn("  \t "), Err(EmptyExpression));
+        assert_eq!(
+            ArithmeticExpr::from_rpn("1 two +"),
+            Err(UnexpectedToken("two".to_string()))
+        );
+        assert_eq!(ArithmeticExpr::from_rpn("1 +"), Err(StackUnderflow));
+        assert_eq!(ArithmeticExpr::from_rpn("neg"), Err(StackUnderflow));
+        assert_eq!(ArithmeticExpr::from_rpn("1 2 3 +"), Err(TrailingTokens(1)));
+        assert_eq!(ArithmeticExpr::from_rpn("1 2 3"), Err(TrailingTokens(2)));
+        assert_eq!(
+            ArithmeticExpr::from_rpn("x_1 x_a +"),
+            Err(InvalidVariable("x_a".to_string()))
+        );
+        assert_eq!(
+            ArithmeticExpr::from_rpn("x_").unwrap_err().to_string(),
+            "invalid variable: x_"
+        );
+    }
+
+    #[test]
+    fn overflow() {
+        for infix in [
+            "2147483647 + 1",
+            "-2147483647 - 2",
+            "65536 * 32768",
+            "x_1 * x_1",
+            "-x_2",
+            "abs(x_2)",
+        ] {
+            let expr = ArithmeticExpr::parse(infix).unwrap();
+            let vars = HashMap::from([(1, 65536), (2, i32::MIN)]);
+            assert_eq!(expr.evaluate(&vars), Err(EvalError::Overflow), "{infix}");
+        }
+    }
+
+    #[test]
+    fn undefined_variable() {
+        let expr = ArithmeticExpr::from_rpn("x_1 x_5 *").unwrap();
+        let vars = HashMap::from([(1, 3)]);
+        assert_eq!(expr.evaluate(&vars), Err(EvalError::UndefinedVariable(5)));
+        assert_eq!(
+            expr.evaluate(&vars).unwrap_err().to_string(),
+            "undefined variable x_5"
+        );
+        assert_eq!(
+            expr.evaluate_nonzero(&vars),
+            Err(EvalError::UndefinedVariable(5))
+        );
+        assert_eq!(
+            expr.evaluate_cached(&vars, &mut EvalCache::new()),
+            Err(EvalError::UndefinedVariable(5))
+        );
+        let columns = HashMap::from([(1, vec![1, 2])]);
+        assert_eq!(
+            expr.evaluate_columns(&columns, 2),
+            Err(EvalError::UndefinedVariable(5))
+        );
+        assert_eq!(expr.evaluate(&HashMap::from([(1, 3), (5, 4)])), Ok(12));
+    }
+
+    #[test]
+    fn infix_precedence() {
+        let vars = HashMap::default();
+        let expr = ArithmeticExpr::parse("3 + 4 * 2").unwrap();
+        assert_eq!(expr.evaluate(&vars), Ok(11));
+        assert_eq!(expr, ArithmeticExpr::from_rpn("3 4 2 * +").unwrap());
+
+        let expr = ArithmeticExpr::parse("(3 + 4) * 2").unwrap();
+        assert_eq!(expr.evaluate(&vars), Ok(14));
+        assert_eq!(expr, ArithmeticExpr::from_rpn("3 4 + 2 *").unwrap());
+
+        let expr = ArithmeticExpr::parse("3 + 4 * (x_1 - 2)").unwrap();
+        assert_eq!(expr, ArithmeticExpr::from_rpn("3 4 x_1 2 - * +").unwrap());
+        assert_eq!(expr.evaluate(&HashMap::from([(1, 5)])), Ok(15));
+
+        for (infix, rpn) in [
+            ("10 - 4 - 3", "10 4 - 3 -"),
+            ("100 / 10 / 5 % 3", "100 10 / 5 / 3 %"),
+            ("2 ^ 3 ^ 2", "2 3 2 ^ ^"),
+            ("-2 ^ 2 * 3", "2 2 ^ neg 3 *"),
+            ("x_1 * -x_2", "x_1 x_2 neg *"),
+            ("abs(x_1 - 3) + sgn x_2", "x_1 3 - abs x_2 sgn +"),
+            ("abs(x_1) ^ 2", "x_1 abs 2 ^"),
+            ("sgn(x_1) ^ 3 * 2", "x_1 sgn 3 ^ 2 *"),
+            ("abs x_1 ^ 2", "x_1 2 ^ abs"),
+            ("((x_10))", "x_10"),
+        ] {
+            assert_eq!(
+                ArithmeticExpr::parse(infix).unwrap(),
+                ArithmeticExpr::from_rpn(rpn).unwrap(),
+                "{infix}"
+            );
+        }
+    }
+
+    #[test]
+    fn infix_parse_errors() {
+        use ParseError::*;
+
+        assert_eq!(ArithmeticExpr::parse(""), Err(UnexpectedEnd));
+        assert_eq!(ArithmeticExpr::parse("3 +"), Err(UnexpectedEnd));
+        assert_eq!(ArithmeticExpr::parse("(3 + 4"), Err(UnexpectedEnd));
+        assert_eq!(
+            ArithmeticExpr::parse("3 + 4)"),
+            Err(UnexpectedToken(")".to_string()))
+        );
+        assert_eq!(
+            ArithmeticExpr::parse("3 4"),
+            Err(UnexpectedToken("4".to_string()))
+        );
+        assert_eq!(
+            ArithmeticExpr::parse("* 2"),
+            Err(UnexpectedToken("*".to_string()))
+        );
+        assert_eq!(
+            ArithmeticExpr::parse("2 $ 3"),
+            Err(UnexpectedToken("$".to_string()))
+        );
+        assert_eq!(
+            ArithmeticExpr::parse("x_1 + y"),
+            Err(UnexpectedToken("y".to_string()))
+        );
+        assert_eq!(
+            ArithmeticExpr::parse("x_1 + x_"),
+            Err(InvalidVariable("x_".to_string()))
+        );
+    }
+
+    #[test]
+    fn infix_display() {
+        for (rpn, infix) in [
+            ("3 4 + 2 *", "(3 + 4) * 2"),
+            ("3 4 2 * +", "3 + 4 * 2"),
+            ("x_1 x_2 x_3 - -", "x_1 - (x_2 - x_3)"),
+            ("x_1 x_2 - x_3 -", "x_1 - x_2 - x_3"),
+            ("x_1 x_2 x_3 + /", "x_1 / (x_2 + x_3)"),
+            ("x_1 x_2 * x_3 % 2 +", "x_1 * x_2 % x_3 + 2"),
+            ("2 3 ^ 2 ^", "(2 ^ 3) ^ 2"),
+            ("2 3 2 ^ ^", "2 ^ 3 ^ 2"),
+            ("x_1 neg 2 ^ neg", "-(-x_1) ^ 2"),
+            ("x_1 x_2 neg *", "x_1 * -x_2"),
+            ("x_1 3 - abs x_2 sgn +", "abs(x_1 - 3) + sgn(x_2)"),
+        ] {
+            assert_eq!(ArithmeticExpr::from_rpn(rpn).unwrap().to_string(), infix);
+        }
+
+        let negative = ArithmeticExpr::Operation {
+            op: Op::Pow,
+            left: Box::new(ArithmeticExpr::Constant(-2)),
+            right: Box::new(ArithmeticExpr::Constant(-1)),
+        };
+        assert_eq!(negative.to_string(), "(-2) ^ (-1)");
+    }
+
+    #[test]
+    fn random_infix_roundtrip() {
+        use rand::{rngs::StdRng, Rng, SeedableRng};
+
+        let mut rng = StdRng::seed_from_u64(295);
+        for _ in 0..1000 {
+            // A negative constant reads back as the negation of a constant,
+            // so compare the printed forms and values rather than the trees.
+            let expr = random_expr(&mut rng, 5, 3);
+            let infix = expr.to_string();
+            let parsed = ArithmeticExpr::parse(&infix).unwrap();
+            assert_eq!(parsed.to_string(), infix);
+
+            let vars = (0..3).map(|i| (i, rng.gen_range(-3..=3))).collect();
+            assert_eq!(parsed.evaluate(&vars), expr.evaluate(&vars));
+        }
+
+        for expr in [
+            ArithmeticExpr::from_rpn("x_1 abs 2 ^").unwrap(),
+            ArithmeticExpr::from_rpn("x_1 sgn x_2 sgn ^").unwrap(),
+            ArithmeticExpr::Constant(i32::MIN),
+            ArithmeticExpr::from_rpn("-2147483648 neg").unwrap(),
+            ArithmeticExpr::from_rpn("-2147483648 2 ^").unwrap(),
+            ArithmeticExpr::from_rpn("x_1 -2147483648 -").unwrap(),
+        ] {
+            assert_eq!(ArithmeticExpr::parse(&expr.to_string()), Ok(expr));
+        }
+        assert_eq!(
+            ArithmeticExpr::parse("2147483648"),
+            Err(ParseError::UnexpectedToken("2147483648".to_string()))
+        );
+    }
+
+    #[test]
+    fn rpn_roundtrip() {
+        for rpn in [
+            "42",
+            "x_12",
+            "3 4 + 2 *",
+            "x_1 x_2 x_3 - -",
+            "x_0 2 ^ x_1 neg abs 7 % /",
+            "x_1 x_2 * sgn x_3 x_1 - x_2 + *",
+        ] {
+            let expr = ArithmeticExpr::from_rpn(rpn).unwrap();
+            assert_eq!(expr.to_rpn(), rpn);
+            assert_eq!(ArithmeticExpr::from_rpn(&expr.to_rpn()).unwrap(), expr);
+        }
+
+        let expr = ArithmeticExpr::parse("-3 * (x_2 - x_10) ^ 2").unwrap();
+        assert_eq!(expr.to_rpn(), "3 neg x_2 x_10 - 2 ^ *");
+        assert_eq!(ArithmeticExpr::from_rpn(&expr.to_rpn()).unwrap(), expr);
+    }
+
+    #[test]
+    fn simplify() {
+        for (infix, simplified) in [
+            ("2 + 3", "5"),
+            ("x_1 * (2 + 3)", "x_1 * 5"),
+            ("x_1 + 0", "x_1"),
+            ("0 + x_1", "x_1"),
+            ("x_1 - 0", "x_1"),
+            ("0 - x_1", "0 - x_1"),
+            ("x_1 * 1", "x_1"),
+            ("1 * x_1", "x_1"),
+            ("x_1 * 0", "0"),
+            ("0 * sgn(x_1)", "0"),
+            ("(x_1 + x_2) * 0", "(x_1 + x_2) * 0"),
+            ("0 * abs(x_1)", "0 * abs(x_1)"),
+            ("(2147483647 + 1) * x_1", "(2147483647 + 1) * x_1"),
+            ("abs(-2147483647 - 1)", "abs(-2147483648)"),
+            ("x_1 * (3 - 2) + x_2 * (4 - 2 * 2)", "x_1"),
+            ("abs(2 - 5) * x_1 ^ (1 - 1)", "3 * x_1 ^ 0"),
+            ("x_1 / (2 - 2) * 0", "x_1 / 0 * 0"),
+            ("(1 / 0) * 1 + 0", "1 / 0"),
+        ] {
+            let expr = ArithmeticExpr::parse(infix).unwrap();
+            assert_eq!(expr.simplify().to_string(), simplified, "{infix}");
+        }
+
+        let expr = ArithmeticExpr::parse("x_1 * (2 * 3 - 5) + x_2 * (7 - 7)").unwrap();
+        let simplified = expr.clone().simplify();
+        assert!(simplified.size() < expr.size());
+        assert_eq!(simplified, ArithmeticExpr::Variable(1));
+        let vars = HashMap::from([(1, 6), (2, -4)]);
+        assert_eq!(simplified.evaluate(&vars), expr.evaluate(&vars));
+    }
+
+    #[test]
+    fn random_simplify_preserves_value() {
+        use rand::{rngs::StdRng, Rng, SeedableRng};
+
+        let mut rng = StdRng::seed_from_u64(297);
+        for _ in 0..1000 {
+            let expr = random_expr(&mut rng, 5, 3);
+            let simplified = expr.clone().simplify();
+            assert!(simplified.size() <= expr.size());
+            for _ in 0..5 {
+                let vars = (0..3).map(|i| (i, rng.gen_range(-3..=3))).collect();
+                assert_eq!(simplified.evaluate(&vars), expr.evaluate(&vars));
+            }
+        }
+    }
+
+    #[test]
+    fn derivative() {
+        let square = ArithmeticExpr::parse("x_1 * x_1").unwrap();
+        let d = square.derivative(1);
+        let twice = ArithmeticExpr::parse("2 * x_1").unwrap();
+        for x in -5..=5 {
+            let vars = HashMap::from([(1, x)]);
+            assert_eq!(d.evaluate(&vars), twice.evaluate(&vars));
+        }
+        assert_eq!(d.simplify().to_string(), "x_1 + x_1");
+        assert_eq!(square.derivative(2).simplify(), ArithmeticExpr::Constant(0));
+
+        for (infix, derivative) in [
+            ("7", "0"),
+            ("x_1 - x_2", "1"),
+            ("3 * x_1 * x_2 + x_2", "3 * x_2 + 3 * x_1 * 0"),
+            ("x_1 ^ 3", "3 * x_1 ^ 2"),
+            ("x_1 ^ 0", "0"),
+            ("x_2 ^ x_3 + x_2 / 2 % x_3", "0"),
+            ("-abs(x_1)", "-sgn(x_1)"),
+            ("sgn(x_1) * x_1", "sgn(x_1)"),
+        ] {
+            let expr = ArithmeticExpr::parse(infix).unwrap();
+            assert_eq!(
+                expr.derivative(1).simplify().to_string(),
+                derivative,
+                "{infix}"
+            );
+        }
+
+        // 2 x^3 - 5 x^2 y + 4, checked against the derivative computed by hand.
+        let poly = ArithmeticExpr::parse("2 * x_1 ^ 3 - 5 * x_1 ^ 2 * x_2 + 4").unwrap();
+        let expected = ArithmeticExpr::parse("6 * x_1 ^ 2 - 10 * x_1 * x_2").unwrap();
+        let d = poly.derivative(1);
+        for x in -3..=3 {
+            for y in -3..=3 {
+                let vars = HashMap::from([(1, x), (2, y)]);
+                assert_eq!(d.evaluate(&vars), expected.evaluate(&vars));
+            }
+        }
+    }
+
+    #[test]
+    fn derivative_of_power() {
+        let expr = ArithmeticExpr::parse("x_1 ^ x_2").unwrap();
+        let d = expr.derivative(1);
+        assert_eq!(d.clone().simplify().to_string(), "x_2 * x_1 ^ (x_2 - 1)");
+        let expected = ArithmeticExpr::parse("x_2 * x_1 ^ (x_2 - 1)").unwrap();
+        for x in -3..=3 {
+            for y in 1..=4 {
+                let vars = HashMap::from([(1, x), (2, y)]);
+                assert_eq!(d.evaluate(&vars), expected.evaluate(&vars));
+            }
+        }
+
+        let min = ArithmeticExpr::from_rpn("x_1 -2147483648 ^").unwrap();
+        assert_eq!(
+            min.derivative(1).simplify().to_string(),
+            "-2147483648 * x_1 ^ (-2147483648 - 1)"
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "`^` has no derivative when its exponent depends on x_1")]
+    fn derivative_of_variable_exponent() {
+        ArithmeticExpr::parse("2 ^ x_1").unwrap().derivative(1);
+    }
+
+    #[test]
+    #[should_panic(expected = "`/` has no derivative")]
+    fn derivative_of_division() {
+        ArithmeticExpr::parse("x_2 / x_1").unwrap().derivative(1);
     }
 }
@@ -0,0 +1,81 @@
+use std::time::{Duration, Instant};
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use exo_5_binary_tree::BinaryTreeMap;
+
+/// A deterministic stream of `n` tokens drawn from a vocabulary of `vocabulary` words.
+fn tokens(n: usize, vocabulary: u64) -> Vec<String> {
+    let mut seed = 0x9e37_79b9_7f4a_7c15_u64;
+    (0..n)
+        .map(|_| {
+            seed = seed
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            format!("w{}", (seed >> 33) % vocabulary)
+        })
+        .collect()
+}
+
+fn count_with_entry(tokens: Vec<String>) -> BinaryTreeMap<String, usize> {
+    let mut counts = BinaryTreeMap::new();
+    for token in tokens {
+        *counts.entry(token).or_insert(0) += 1;
+    }
+    counts
+}
+
+fn count_with_get_insert(tokens: Vec<String>) -> BinaryTreeMap<String, usize> {
+    let mut counts = BinaryTreeMap::new();
+    for token in tokens {
+        let count = counts.get(&token).copied().unwrap_or(0);
+        counts.insert(token, count + 1);
+    }
+    counts
+}
+
+/// The fastest of a few runs of `count` on `stream`, excluding the copy of the stream.
+fn best_time(
+    stream: &[String],
+    count: fn(Vec<String>) -> BinaryTreeMap<String, usize>,
+) -> Duration {
+    (0..5)
+        .map(|_| {
+            let tokens = stream.to_vec();
+            let start = Instant::now();
+            criterion::black_box(count(tokens));
+            start.elapsed()
+        })
+        .min()
+        .unwrap()
+}
+
+fn word_count(c: &mut Criterion) {
+    for vocabulary in [100, 10_000] {
+        let stream = tokens(100_000, vocabulary);
+        assert!(
+            count_with_entry(stream.clone()) == count_with_get_insert(stream.clone()),
+            "both ways of counting must build the same map"
+        );
+
+        let mut group = c.benchmark_group(format!("word_count/{vocabulary}"));
+        group.throughput(Throughput::Elements(stream.len() as u64));
+        group.bench_function("entry", |b| {
+            b.iter_batched(|| stream.clone(), count_with_entry, BatchSize::LargeInput)
+        });
+        group.bench_function("get_insert", |b| {
+            b.iter_batched(
+                || stream.clone(),
+                count_with_get_insert,
+                BatchSize::LargeInput,
+            )
+        });
+        group.finish();
+
+        let speedup = best_time(&stream, count_with_get_insert).as_secs_f64()
+            / best_time(&stream, count_with_entry).as_secs_f64();
+        println!("word_count/{vocabulary}: entry is {speedup:.2}x as fast as get_insert");
+    }
+}
+
+criterion_group!(benches, word_count);
+criterion_main!(benches);
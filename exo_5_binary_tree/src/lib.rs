@@ -1,93 +1,1685 @@
+use std::borrow::Borrow;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt;
+use std::ops::{Add, Bound, Index, RangeBounds};
+
 /// Maps keys of type `K` to values of type `V`.
+#[derive(Clone)]
 pub struct BinaryTreeMap<K, V> {
-    // TODO: add struct members,
-    // remove _marker below
-    _marker: (K, V),
+    root: BinaryTreeNode<K, V>,
+    size: usize,
 }
 
-/// Hint: you may need to define additional structs/enums,
-/// or add trait bounds, etc.
-impl<K, V> BinaryTreeMap<K, V> {
+/// A possibly empty binary search tree, kept AVL-balanced: the heights of
+/// the two subtrees of every node differ by at most one.
+#[derive(Clone)]
+enum BinaryTreeNode<K, V> {
+    Leaf,
+    Node(Box<Node<K, V>>),
+}
+
+#[derive(Clone)]
+struct Node<K, V> {
+    key: K,
+    value: V,
+    left: BinaryTreeNode<K, V>,
+    right: BinaryTreeNode<K, V>,
+    /// Number of nodes on the longest path down from this one.
+    height: usize,
+    /// Number of nodes in the subtree rooted at this one.
+    count: usize,
+}
+
+/// Directions from the root of a subtree down to one of its nodes,
+/// `Less` for the left child and `Greater` for the right one.
+type Path = VecDeque<Ordering>;
+
+/// Summary of the shape of a [`BinaryTreeMap`], see [`BinaryTreeMap::shape_stats`].
+///
+/// Depths are counted in nodes, so a map with a single entry has
+/// `height == min_depth == 1`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ShapeStats {
+    /// Number of nodes on the longest root-to-leaf path.
+    pub height: usize,
+    /// Number of entries in the map.
+    pub size: usize,
+    /// Number of nodes on the shortest root-to-leaf path.
+    pub min_depth: usize,
+    /// Average number of nodes on a root-to-leaf path.
+    pub average_leaf_depth: f64,
+    /// Whether the heights of the two subtrees of every node differ by at most one.
+    pub balanced: bool,
+}
+
+impl<K: Ord, V> BinaryTreeNode<K, V> {
+    /// Insert `key` at the end of `path`, which must lead from the root of
+    /// this subtree to the empty slot where `key` belongs, as found by
+    /// [`BinaryTreeNode::path_to`]. Rebalances on the way back up and returns
+    /// the path from the root of this subtree to the new node.
+    ///
+    /// The descent follows `path` without comparing keys, and is iterative: the
+    /// nodes along the way are detached onto an explicit stack, then reattached
+    /// and rebalanced from the bottom up.
+    fn insert_at(&mut self, path: &[Ordering], key: K, value: V) -> Path {
+        let mut spine = Vec::with_capacity(path.len());
+        let mut tree = std::mem::replace(self, BinaryTreeNode::Leaf);
+        for &side in path {
+            let BinaryTreeNode::Node(mut node) = tree else {
+                panic!("path leads to an empty slot");
+            };
+            tree = std::mem::replace(node.child_mut(side), BinaryTreeNode::Leaf);
+            spine.push((node, side));
+        }
+        debug_assert!(
+            matches!(tree, BinaryTreeNode::Leaf),
+            "path leads to an empty slot"
+        );
+
+        tree = BinaryTreeNode::Node(Box::new(Node::new(
+            key,
+            value,
+            BinaryTreeNode::Leaf,
+            BinaryTreeNode::Leaf,
+        )));
+        let mut path = Path::new();
+        while let Some((mut node, side)) = spine.pop() {
+            *node.child_mut(side) = tree;
+            tree = BinaryTreeNode::Node(node);
+            path.push_front(side);
+            tree.rebalance(Some(&mut path));
+        }
+        *self = tree;
+        path
+    }
+
+    /// The path from the root of this subtree to the node holding `key`, or
+    /// if there is none, to the empty slot where it belongs.
+    fn path_to(&self, key: &K) -> Result<Vec<Ordering>, Vec<Ordering>> {
+        let mut path = Vec::new();
+        let mut tree = self;
+        while let BinaryTreeNode::Node(node) = tree {
+            let side = key.cmp(&node.key);
+            if side == Ordering::Equal {
+                return Ok(path);
+            }
+            path.push(side);
+            tree = node.child(side);
+        }
+        Err(path)
+    }
+
+    fn get<Q: Ord + ?Sized>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+    {
+        self.get_key_value(key).map(|(_, value)| value)
+    }
+
+    fn get_key_value<Q: Ord + ?Sized>(&self, key: &Q) -> Option<(&K, &V)>
+    where
+        K: Borrow<Q>,
+    {
+        let mut tree = self;
+        while let BinaryTreeNode::Node(node) = tree {
+            tree = match key.cmp(node.key.borrow()) {
+                Ordering::Less => &node.left,
+                Ordering::Greater => &node.right,
+                Ordering::Equal => return Some((&node.key, &node.value)),
+            };
+        }
+        None
+    }
+
+    fn get_mut<Q: Ord + ?Sized>(&mut self, key: &Q) -> Option<&mut V>
+    where
+        K: Borrow<Q>,
+    {
+        let mut tree = self;
+        while let BinaryTreeNode::Node(node) = tree {
+            tree = match key.cmp(node.key.borrow()) {
+                Ordering::Less => &mut node.left,
+                Ordering::Greater => &mut node.right,
+                Ordering::Equal => return Some(&mut node.value),
+            };
+        }
+        None
+    }
+
+    fn remove<Q: Ord + ?Sized>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+    {
+        let BinaryTreeNode::Node(node) = self else {
+            return None;
+        };
+        let removed = match key.cmp(node.key.borrow()) {
+            Ordering::Less => node.left.remove(key),
+            Ordering::Greater => node.right.remove(key),
+            Ordering::Equal => return self.remove_root().map(|(_, value)| value),
+        };
+        self.rebalance(None);
+        removed
+    }
+
+    /// Remove the entry at the end of `path`, which must lead to a node.
+    fn remove_at(&mut self, path: &[Ordering]) -> (K, V) {
+        let Some((&side, rest)) = path.split_first() else {
+            return self.remove_root().expect("path leads to a node");
+        };
+        let BinaryTreeNode::Node(node) = self else {
+            panic!("path leads to a node");
+        };
+        let entry = node.child_mut(side).remove_at(rest);
+        self.rebalance(None);
+        entry
+    }
+
+    fn range_sum<R: RangeBounds<K>>(&self, range: &R) -> V
+    where
+        V: Default + Add<Output = V> + Copy,
+    {
+        let BinaryTreeNode::Node(node) = self else {
+            return V::default();
+        };
+        let mut sum = V::default();
+        if left_may_intersect(range, &node.key) {
+            sum = sum + node.left.range_sum(range);
+        }
+        if range.contains(&node.key) {
+            sum = sum + node.value;
+        }
+        if right_may_intersect(range, &node.key) {
+            sum = sum + node.right.range_sum(range);
+        }
+        sum
+    }
+
+    /// Number of nodes from the root of this subtree down to the one holding `key`.
+    fn depth_of(&self, key: &K) -> Option<usize> {
+        let BinaryTreeNode::Node(node) = self else {
+            return None;
+        };
+        match key.cmp(&node.key) {
+            Ordering::Less => node.left.depth_of(key).map(|d| d + 1),
+            Ordering::Greater => node.right.depth_of(key).map(|d| d + 1),
+            Ordering::Equal => Some(1),
+        }
+    }
+
+    /// Remove the entry at the root of this subtree.
+    fn remove_root(&mut self) -> Option<(K, V)> {
+        let BinaryTreeNode::Node(node) = std::mem::replace(self, BinaryTreeNode::Leaf) else {
+            return None;
+        };
+        let Node {
+            key,
+            value,
+            left,
+            mut right,
+            ..
+        } = *node;
+        *self = match right.pop_smallest() {
+            Some((k, v)) => BinaryTreeNode::Node(Box::new(Node::new(k, v, left, right))),
+            None => left,
+        };
+        self.rebalance(None);
+        Some((key, value))
+    }
+
+    /// Remove the entry with the smallest key in this subtree.
+    fn pop_smallest(&mut self) -> Option<(K, V)> {
+        let BinaryTreeNode::Node(node) = self else {
+            return None;
+        };
+        if let BinaryTreeNode::Node(_) = node.left {
+            let entry = node.left.pop_smallest();
+            self.rebalance(None);
+            return entry;
+        }
+        match std::mem::replace(self, BinaryTreeNode::Leaf) {
+            BinaryTreeNode::Node(node) => {
+                let Node {
+                    key, value, right, ..
+                } = *node;
+                *self = right;
+                Some((key, value))
+            }
+            BinaryTreeNode::Leaf => unreachable!(),
+        }
+    }
+
+    /// Remove the entry with the largest key in this subtree.
+    fn pop_largest(&mut self) -> Option<(K, V)> {
+        let BinaryTreeNode::Node(node) = self else {
+            return None;
+        };
+        if let BinaryTreeNode::Node(_) = node.right {
+            let entry = node.right.pop_largest();
+            self.rebalance(None);
+            return entry;
+        }
+        match std::mem::replace(self, BinaryTreeNode::Leaf) {
+            BinaryTreeNode::Node(node) => {
+                let Node {
+                    key, value, left, ..
+                } = *node;
+                *self = left;
+                Some((key, value))
+            }
+            BinaryTreeNode::Leaf => unreachable!(),
+        }
+    }
+}
+
+impl<K, V> Node<K, V> {
+    fn new(key: K, value: V, left: BinaryTreeNode<K, V>, right: BinaryTreeNode<K, V>) -> Self {
+        let mut node = Node {
+            key,
+            value,
+            left,
+            right,
+            height: 0,
+            count: 0,
+        };
+        node.update();
+        node
+    }
+
+    fn child(&self, side: Ordering) -> &BinaryTreeNode<K, V> {
+        match side {
+            Ordering::Less => &self.left,
+            _ => &self.right,
+        }
+    }
+
+    fn child_mut(&mut self, side: Ordering) -> &mut BinaryTreeNode<K, V> {
+        match side {
+            Ordering::Less => &mut self.left,
+            _ => &mut self.right,
+        }
+    }
+
+    /// Recompute the height and count of this node from its children.
+    fn update(&mut self) {
+        self.height = 1 + self.left.height().max(self.right.height());
+        self.count = 1 + self.left.size() + self.right.size();
+    }
+}
+
+/// Whether keys smaller than `key` may lie in `range`.
+fn left_may_intersect<K: Ord, R: RangeBounds<K>>(range: &R, key: &K) -> bool {
+    match range.start_bound() {
+        Bound::Included(start) | Bound::Excluded(start) => start < key,
+        Bound::Unbounded => true,
+    }
+}
+
+/// Whether `key` is not below the start of `range`.
+fn after_start<K: Ord, R: RangeBounds<K>>(range: &R, key: &K) -> bool {
+    match range.start_bound() {
+        Bound::Included(start) => start <= key,
+        Bound::Excluded(start) => start < key,
+        Bound::Unbounded => true,
+    }
+}
+
+/// Whether keys greater than `key` may lie in `range`.
+fn right_may_intersect<K: Ord, R: RangeBounds<K>>(range: &R, key: &K) -> bool {
+    match range.end_bound() {
+        Bound::Included(end) | Bound::Excluded(end) => key < end,
+        Bound::Unbounded => true,
+    }
+}
+
+impl<K, V> BinaryTreeNode<K, V> {
+    /// Build a height-balanced tree from the next `n` entries of `entries`,
+    /// which must be sorted by key.
+    fn from_sorted_iter<I: Iterator<Item = (K, V)>>(entries: &mut I, n: usize) -> Self {
+        if n == 0 {
+            return BinaryTreeNode::Leaf;
+        }
+        let left = Self::from_sorted_iter(entries, n / 2);
+        let (key, value) = entries.next().expect("not enough entries");
+        let right = Self::from_sorted_iter(entries, n - n / 2 - 1);
+        BinaryTreeNode::Node(Box::new(Node::new(key, value, left, right)))
+    }
+
+    /// Join two balanced trees into a balanced one, where the keys of `left`
+    /// are smaller than `key` and those of `right` are greater.
+    ///
+    /// The smaller tree is grafted with `key` along the facing spine of the
+    /// taller one, at the first node not more than one level taller, and the
+    /// spine is rebalanced on the way back up.
+    fn join(left: Self, key: K, value: V, right: Self) -> Self {
+        let (left_height, right_height) = (left.height(), right.height());
+        if left_height > right_height + 1 {
+            let BinaryTreeNode::Node(mut node) = left else {
+                unreachable!("the taller tree is not empty");
+            };
+            let inner = std::mem::replace(&mut node.right, BinaryTreeNode::Leaf);
+            node.right = Self::join(inner, key, value, right);
+            let mut tree = BinaryTreeNode::Node(node);
+            tree.rebalance(None);
+            tree
+        } else if right_height > left_height + 1 {
+            let BinaryTreeNode::Node(mut node) = right else {
+                unreachable!("the taller tree is not empty");
+            };
+            let inner = std::mem::replace(&mut node.left, BinaryTreeNode::Leaf);
+            node.left = Self::join(left, key, value, inner);
+            let mut tree = BinaryTreeNode::Node(node);
+            tree.rebalance(None);
+            tree
+        } else {
+            BinaryTreeNode::Node(Box::new(Node::new(key, value, left, right)))
+        }
+    }
+
+    /// Number of nodes on the longest path from the root of this subtree.
+    fn height(&self) -> usize {
+        match self {
+            BinaryTreeNode::Leaf => 0,
+            BinaryTreeNode::Node(node) => node.height,
+        }
+    }
+
+    /// Number of nodes in this subtree, as stored in its root.
+    fn size(&self) -> usize {
+        match self {
+            BinaryTreeNode::Leaf => 0,
+            BinaryTreeNode::Node(node) => node.count,
+        }
+    }
+
+    /// Height of the left subtree minus height of the right one.
+    fn balance_factor(&self) -> isize {
+        match self {
+            BinaryTreeNode::Leaf => 0,
+            BinaryTreeNode::Node(node) => {
+                node.left.height() as isize - node.right.height() as isize
+            }
+        }
+    }
+
+    /// The node at the end of `path`, which must lead to a node.
+    fn node_at(&self, path: impl IntoIterator<Item = Ordering>) -> &Node<K, V> {
+        let mut tree = self;
+        for side in path {
+            let BinaryTreeNode::Node(node) = tree else {
+                panic!("path leads to a node");
+            };
+            tree = node.child(side);
+        }
+        match tree {
+            BinaryTreeNode::Node(node) => node,
+            BinaryTreeNode::Leaf => panic!("path leads to a node"),
+        }
+    }
+
+    /// The node at the end of `path`, which must lead to a node.
+    fn node_at_mut(&mut self, path: impl IntoIterator<Item = Ordering>) -> &mut Node<K, V> {
+        let mut tree = self;
+        for side in path {
+            let BinaryTreeNode::Node(node) = tree else {
+                panic!("path leads to a node");
+            };
+            tree = node.child_mut(side);
+        }
+        match tree {
+            BinaryTreeNode::Node(node) => node,
+            BinaryTreeNode::Leaf => panic!("path leads to a node"),
+        }
+    }
+
+    /// Rotate this subtree so that the root of its child on `side` becomes its root.
+    ///
+    /// If given, `path` is updated to keep leading to the same node.
+    fn rotate(&mut self, side: Ordering, path: Option<&mut Path>) {
+        let BinaryTreeNode::Node(mut root) = std::mem::replace(self, BinaryTreeNode::Leaf) else {
+            panic!("cannot rotate an empty tree");
+        };
+        let BinaryTreeNode::Node(mut child) =
+            std::mem::replace(root.child_mut(side), BinaryTreeNode::Leaf)
+        else {
+            panic!("cannot rotate towards an empty subtree");
+        };
+        let other = side.reverse();
+        *root.child_mut(side) = std::mem::replace(child.child_mut(other), BinaryTreeNode::Leaf);
+        root.update();
+        *child.child_mut(other) = BinaryTreeNode::Node(root);
+        child.update();
+        *self = BinaryTreeNode::Node(child);
+
+        let Some(path) = path else {
+            return;
+        };
+        match path.pop_front() {
+            // The old root moved down to the other side of the child.
+            None => path.push_front(other),
+            Some(first) if first == other => {
+                path.push_front(other);
+                path.push_front(other);
+            }
+            // The inner subtree of the child moved under the old root.
+            Some(_) if path.front() == Some(&other) => {
+                path.pop_front();
+                path.push_front(side);
+                path.push_front(other);
+            }
+            Some(_) => {}
+        }
+    }
+
+    /// Restore the balance of this subtree, whose subtrees are balanced and
+    /// differ in height by at most two, and update its height and count.
+    ///
+    /// If given, `path` is updated to keep leading to the same node.
+    fn rebalance(&mut self, mut path: Option<&mut Path>) {
+        let BinaryTreeNode::Node(node) = self else {
+            return;
+        };
+        node.update();
+        let heavy = match node.left.height() as isize - node.right.height() as isize {
+            2 => Ordering::Less,
+            -2 => Ordering::Greater,
+            _ => return,
+        };
+        let child = node.child_mut(heavy);
+        let leans_inward = match heavy {
+            Ordering::Less => child.balance_factor() < 0,
+            _ => child.balance_factor() > 0,
+        };
+        if leans_inward {
+            match path.as_deref_mut() {
+                Some(path) if path.front() == Some(&heavy) => {
+                    path.pop_front();
+                    child.rotate(heavy.reverse(), Some(&mut *path));
+                    path.push_front(heavy);
+                }
+                _ => child.rotate(heavy.reverse(), None),
+            }
+        }
+        self.rotate(heavy, path);
+    }
+
+    /// Check the stored height and count of every node of this subtree, and
+    /// that it is balanced. Returns its height and count.
+    #[cfg(any(test, debug_assertions))]
+    fn check_shape(&self) -> (usize, usize) {
+        let BinaryTreeNode::Node(node) = self else {
+            return (0, 0);
+        };
+        let (left_height, left_count) = node.left.check_shape();
+        let (right_height, right_count) = node.right.check_shape();
+        let (height, count) = (
+            1 + left_height.max(right_height),
+            1 + left_count + right_count,
+        );
+        assert_eq!(node.height, height, "wrong stored height");
+        assert_eq!(node.count, count, "wrong stored subtree count");
+        assert!(
+            left_height.abs_diff(right_height) <= 1,
+            "subtree heights differ by {}",
+            left_height.abs_diff(right_height)
+        );
+        (height, count)
+    }
+
+    /// Apply `f` to the values of this subtree in key order, keeping its shape.
+    fn map_values<W, F: FnMut(V) -> W>(self, f: &mut F) -> BinaryTreeNode<K, W> {
+        let BinaryTreeNode::Node(node) = self else {
+            return BinaryTreeNode::Leaf;
+        };
+        let Node {
+            key,
+            value,
+            left,
+            right,
+            height,
+            count,
+        } = *node;
+        let left = left.map_values(f);
+        let value = f(value);
+        let right = right.map_values(f);
+        BinaryTreeNode::Node(Box::new(Node {
+            key,
+            value,
+            left,
+            right,
+            height,
+            count,
+        }))
+    }
+
+    /// Count the nodes of this subtree by visiting all of them.
+    fn count(&self) -> usize {
+        match self {
+            BinaryTreeNode::Leaf => 0,
+            BinaryTreeNode::Node(node) => 1 + node.left.count() + node.right.count(),
+        }
+    }
+
+    /// Returns `(height, min_depth, leaf_count, leaf_depth_sum, balanced)`,
+    /// with depths relative to this subtree.
+    fn shape(&self) -> (usize, usize, usize, usize, bool) {
+        let BinaryTreeNode::Node(node) = self else {
+            return (0, 0, 0, 0, true);
+        };
+        match (&node.left, &node.right) {
+            (BinaryTreeNode::Leaf, BinaryTreeNode::Leaf) => (1, 1, 1, 1, true),
+            (BinaryTreeNode::Leaf, child) | (child, BinaryTreeNode::Leaf) => {
+                let (h, min, leaves, sum, balanced) = child.shape();
+                (h + 1, min + 1, leaves, sum + leaves, balanced && h <= 1)
+            }
+            (left, right) => {
+                let (lh, lmin, lleaves, lsum, lbal) = left.shape();
+                let (rh, rmin, rleaves, rsum, rbal) = right.shape();
+                let leaves = lleaves + rleaves;
+                (
+                    lh.max(rh) + 1,
+                    lmin.min(rmin) + 1,
+                    leaves,
+                    lsum + rsum + leaves,
+                    lbal && rbal && lh.abs_diff(rh) <= 1,
+                )
+            }
+        }
+    }
+}
+
+impl<K: Ord, V> Default for BinaryTreeMap<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K: Ord, V> BinaryTreeMap<K, V> {
     pub fn new() -> Self {
-        todo!("Create a new empty map")
+        Self {
+            root: BinaryTreeNode::Leaf,
+            size: 0,
+        }
     }
 
     pub fn len(&self) -> usize {
-        todo!("Return the number of elements in the map")
+        self.size
     }
 
     pub fn is_empty(&self) -> bool {
-        todo!("Whether the map is empty")
+        self.size == 0
+    }
+
+    /// Remove all entries from the map.
+    pub fn clear(&mut self) {
+        self.root = BinaryTreeNode::Leaf;
+        self.size = 0;
     }
 
     pub fn insert(&mut self, key: K, value: V) -> Option<V> {
-        todo!("Insert the key value pair in the map, return the previous value if there was one")
+        match self.entry(key) {
+            Entry::Occupied(mut entry) => Some(entry.insert(value)),
+            Entry::Vacant(entry) => {
+                entry.insert_entry(value);
+                None
+            }
+        }
+    }
+
+    pub fn get<Q: Ord + ?Sized>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+    {
+        self.root.get(key)
+    }
+
+    /// Look up `key`, returning the key stored in the map along with its value.
+    pub fn get_key_value<Q: Ord + ?Sized>(&self, key: &Q) -> Option<(&K, &V)>
+    where
+        K: Borrow<Q>,
+    {
+        self.root.get_key_value(key)
+    }
+
+    pub fn get_mut<Q: Ord + ?Sized>(&mut self, key: &Q) -> Option<&mut V>
+    where
+        K: Borrow<Q>,
+    {
+        self.root.get_mut(key)
+    }
+
+    /// Look up each of `keys`, in order.
+    pub fn get_many<'a, Q: Ord + ?Sized>(&'a self, keys: &[&Q]) -> Vec<Option<&'a V>>
+    where
+        K: Borrow<Q>,
+    {
+        keys.iter().map(|key| self.root.get(*key)).collect()
+    }
+
+    pub fn contains<Q: Ord + ?Sized>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+    {
+        self.get(key).is_some()
+    }
+
+    /// The entry with the smallest key, found by walking down the left spine.
+    pub fn first_key_value(&self) -> Option<(&K, &V)> {
+        let mut tree = &self.root;
+        let mut last = None;
+        while let BinaryTreeNode::Node(node) = tree {
+            last = Some(node);
+            tree = &node.left;
+        }
+        last.map(|node| (&node.key, &node.value))
+    }
+
+    /// The entry with the largest key, found by walking down the right spine.
+    pub fn last_key_value(&self) -> Option<(&K, &V)> {
+        let mut tree = &self.root;
+        let mut last = None;
+        while let BinaryTreeNode::Node(node) = tree {
+            last = Some(node);
+            tree = &node.right;
+        }
+        last.map(|node| (&node.key, &node.value))
+    }
+
+    pub fn remove<Q: Ord + ?Sized>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+    {
+        let old = self.root.remove(key);
+        if old.is_some() {
+            self.size -= 1;
+        }
+        old
+    }
+
+    /// The entry with the largest key less than or equal to `key`.
+    pub fn floor(&self, key: &K) -> Option<(&K, &V)> {
+        let mut best = None;
+        let mut tree = &self.root;
+        while let BinaryTreeNode::Node(node) = tree {
+            tree = match key.cmp(&node.key) {
+                Ordering::Less => &node.left,
+                Ordering::Greater => {
+                    best = Some(node);
+                    &node.right
+                }
+                Ordering::Equal => return Some((&node.key, &node.value)),
+            };
+        }
+        best.map(|node| (&node.key, &node.value))
+    }
+
+    /// The entry with the smallest key greater than or equal to `key`.
+    pub fn ceiling(&self, key: &K) -> Option<(&K, &V)> {
+        let mut best = None;
+        let mut tree = &self.root;
+        while let BinaryTreeNode::Node(node) = tree {
+            tree = match key.cmp(&node.key) {
+                Ordering::Less => {
+                    best = Some(node);
+                    &node.left
+                }
+                Ordering::Greater => &node.right,
+                Ordering::Equal => return Some((&node.key, &node.value)),
+            };
+        }
+        best.map(|node| (&node.key, &node.value))
+    }
+
+    /// The number of keys strictly less than `key`.
+    pub fn rank(&self, key: &K) -> usize {
+        let mut rank = 0;
+        let mut tree = &self.root;
+        while let BinaryTreeNode::Node(node) = tree {
+            tree = match key.cmp(&node.key) {
+                Ordering::Less => &node.left,
+                Ordering::Greater => {
+                    rank += node.left.size() + 1;
+                    &node.right
+                }
+                Ordering::Equal => return rank + node.left.size(),
+            };
+        }
+        rank
+    }
+
+    /// The number of keys in `range`, computed from subtree sizes without visiting them.
+    pub fn count_range<R: RangeBounds<K>>(&self, range: R) -> usize {
+        let below_end = match range.end_bound() {
+            Bound::Included(end) => self.count_prefix(|k| k <= end),
+            Bound::Excluded(end) => self.count_prefix(|k| k < end),
+            Bound::Unbounded => self.size,
+        };
+        let below_start = match range.start_bound() {
+            Bound::Included(start) => self.count_prefix(|k| k < start),
+            Bound::Excluded(start) => self.count_prefix(|k| k <= start),
+            Bound::Unbounded => 0,
+        };
+        below_end.saturating_sub(below_start)
+    }
+
+    /// The number of keys for which `below` holds, given that it holds for
+    /// every key smaller than one for which it does.
+    fn count_prefix(&self, below: impl Fn(&K) -> bool) -> usize {
+        let mut count = 0;
+        let mut tree = &self.root;
+        while let BinaryTreeNode::Node(node) = tree {
+            if below(&node.key) {
+                count += node.left.size() + 1;
+                tree = &node.right;
+            } else {
+                tree = &node.left;
+            }
+        }
+        count
+    }
+
+    /// The entry with the `k`-th smallest key, starting from 0.
+    pub fn select(&self, mut k: usize) -> Option<(&K, &V)> {
+        let mut tree = &self.root;
+        while let BinaryTreeNode::Node(node) = tree {
+            let left = node.left.size();
+            tree = match k.cmp(&left) {
+                Ordering::Less => &node.left,
+                Ordering::Greater => {
+                    k -= left + 1;
+                    &node.right
+                }
+                Ordering::Equal => return Some((&node.key, &node.value)),
+            };
+        }
+        None
+    }
+
+    /// The largest key strictly less than `key`, whether or not `key` is in the map.
+    pub fn predecessor(&self, key: &K) -> Option<&K> {
+        let mut best = None;
+        let mut tree = &self.root;
+        while let BinaryTreeNode::Node(node) = tree {
+            if node.key < *key {
+                best = Some(&node.key);
+                tree = &node.right;
+            } else {
+                tree = &node.left;
+            }
+        }
+        best
+    }
+
+    /// The smallest key strictly greater than `key`, whether or not `key` is in the map.
+    pub fn successor(&self, key: &K) -> Option<&K> {
+        let mut best = None;
+        let mut tree = &self.root;
+        while let BinaryTreeNode::Node(node) = tree {
+            if node.key > *key {
+                best = Some(&node.key);
+                tree = &node.left;
+            } else {
+                tree = &node.right;
+            }
+        }
+        best
+    }
+
+    /// Remove and return the entry with the smallest key.
+    pub fn pop_first(&mut self) -> Option<(K, V)> {
+        let entry = self.root.pop_smallest()?;
+        self.size -= 1;
+        Some(entry)
+    }
+
+    /// Remove and return the entry with the largest key.
+    pub fn pop_last(&mut self) -> Option<(K, V)> {
+        let entry = self.root.pop_largest()?;
+        self.size -= 1;
+        Some(entry)
+    }
+
+    /// Build a balanced map from entries sorted by strictly increasing key, in linear time.
+    pub fn from_sorted(entries: Vec<(K, V)>) -> Self {
+        debug_assert!(
+            entries.windows(2).all(|w| w[0].0 < w[1].0),
+            "keys must be strictly increasing"
+        );
+        let size = entries.len();
+        BinaryTreeMap {
+            root: BinaryTreeNode::from_sorted_iter(&mut entries.into_iter(), size),
+            size,
+        }
+    }
+
+    /// Split the map into its `i` smallest entries and the remaining ones,
+    /// both rebuilt as balanced trees.
+    pub fn split_at_index(self, i: usize) -> (BinaryTreeMap<K, V>, BinaryTreeMap<K, V>) {
+        let mut entries = self.into_iter();
+        let first: Vec<(K, V)> = entries.by_ref().take(i).collect();
+        let second: Vec<(K, V)> = entries.collect();
+        (Self::from_sorted(first), Self::from_sorted(second))
+    }
+
+    /// Move the entries with keys greater than or equal to `key` into a new map.
+    ///
+    /// Both halves are rebuilt as balanced trees.
+    pub fn split_off(&mut self, key: &K) -> BinaryTreeMap<K, V> {
+        let mut entries: Vec<(K, V)> = self.drain().collect();
+        let high = entries.split_off(entries.partition_point(|(k, _)| k < key));
+        *self = Self::from_sorted(entries);
+        Self::from_sorted(high)
+    }
+
+    /// Move all entries of `other` into the map, leaving `other` empty.
+    ///
+    /// On a key collision, the value from `other` wins. The merged entries
+    /// are rebuilt as a balanced tree.
+    pub fn append(&mut self, other: &mut BinaryTreeMap<K, V>) {
+        let mut theirs = other.drain().peekable();
+        let mut merged = Vec::new();
+        for entry in self.drain() {
+            while let Some(next) = theirs.next_if(|(k, _)| *k < entry.0) {
+                merged.push(next);
+            }
+            match theirs.next_if(|(k, _)| *k == entry.0) {
+                Some(replacement) => merged.push(replacement),
+                None => merged.push(entry),
+            }
+        }
+        merged.extend(theirs);
+        *self = Self::from_sorted(merged);
+    }
+
+    /// Append entries whose keys are all greater than the current maximum.
+    ///
+    /// The new entries must be sorted by strictly increasing key. They are
+    /// built into a balanced subtree, which is grafted onto the right spine
+    /// of the tree at the node of matching height before rebalancing back up,
+    /// in time linear in the number of new entries plus the height of the tree.
+    pub fn append_sorted_greater<I: IntoIterator<Item = (K, V)>>(&mut self, iter: I) {
+        let entries: Vec<(K, V)> = iter.into_iter().collect();
+        if entries.is_empty() {
+            return;
+        }
+
+        debug_assert!(
+            self.last_key_value()
+                .is_none_or(|(max, _)| *max < entries[0].0),
+            "appended keys must be greater than the current maximum"
+        );
+        debug_assert!(
+            entries.windows(2).all(|w| w[0].0 < w[1].0),
+            "appended keys must be strictly increasing"
+        );
+
+        if self.is_empty() {
+            *self = Self::from_sorted(entries);
+            return;
+        }
+        let n = entries.len();
+        let mut entries = entries.into_iter();
+        let (key, value) = entries.next().unwrap();
+        let right = BinaryTreeNode::from_sorted_iter(&mut entries, n - 1);
+        let left = std::mem::replace(&mut self.root, BinaryTreeNode::Leaf);
+        self.root = BinaryTreeNode::join(left, key, value, right);
+        self.size += n;
+    }
+
+    /// Sum the values whose keys lie in `range`, skipping subtrees outside of it.
+    pub fn range_sum<R: RangeBounds<K>>(&self, range: R) -> V
+    where
+        V: Default + Add<Output = V> + Copy,
+    {
+        self.root.range_sum(&range)
+    }
+
+    /// Iterate over the entries whose keys lie in `range`, in ascending key order.
+    ///
+    /// Subtrees entirely outside of `range` are never visited.
+    pub fn range<R: RangeBounds<K>>(&self, range: R) -> impl Iterator<Item = (&K, &V)> {
+        let mut iter = BinaryTreeMapRange {
+            stack: Vec::new(),
+            range,
+        };
+        iter.push_left_spine(&self.root);
+        iter
+    }
+
+    /// Consume the map, applying `f` to each value in key order.
+    ///
+    /// The keys and the shape of the tree are kept as they are.
+    pub fn map_values<W, F: FnMut(V) -> W>(self, mut f: F) -> BinaryTreeMap<K, W> {
+        BinaryTreeMap {
+            root: self.root.map_values(&mut f),
+            size: self.size,
+        }
+    }
+
+    /// Consume the map, replacing each entry with the entries produced by `f`.
+    ///
+    /// Entries are processed in key order and inserted into a new map, so a
+    /// later entry overwrites an earlier one with the same key.
+    pub fn flat_map_values<W, I, F>(self, mut f: F) -> BinaryTreeMap<K, W>
+    where
+        I: IntoIterator<Item = (K, W)>,
+        F: FnMut(K, V) -> I,
+    {
+        let mut map = BinaryTreeMap::new();
+        for (key, value) in self {
+            for (k, w) in f(key, value) {
+                map.insert(k, w);
+            }
+        }
+        map
+    }
+
+    /// Remove all entries from the map, yielding them in ascending key order.
+    ///
+    /// The map is emptied right away, so it is empty and reusable once the
+    /// iterator is dropped, even if not all entries were consumed.
+    pub fn drain(&mut self) -> impl Iterator<Item = (K, V)> + '_ {
+        std::mem::take(self).into_iter()
+    }
+
+    /// Keep only the entries for which `f` returns true, returning how many were removed.
+    ///
+    /// The remaining entries are rebuilt into a balanced tree.
+    pub fn retain_returning<F: FnMut(&K, &mut V) -> bool>(&mut self, mut f: F) -> usize {
+        let before = self.size;
+        let kept: Vec<(K, V)> = self
+            .drain()
+            .filter_map(|(k, mut v)| f(&k, &mut v).then_some((k, v)))
+            .collect();
+        *self = Self::from_sorted(kept);
+        before - self.size
+    }
+
+    /// Keep only the entries for which `f` returns true.
+    pub fn retain<F: FnMut(&K, &mut V) -> bool>(&mut self, f: F) {
+        self.retain_returning(f);
+    }
+
+    /// Rebuild the map into a height-balanced tree, with the median entry at the root.
+    pub fn rebalance(&mut self) {
+        let entries = self.drain().collect();
+        *self = Self::from_sorted(entries);
+    }
+
+    /// Count the entries of the tree, fix the stored size if it was wrong, and return it.
+    pub fn recount_size(&mut self) -> usize {
+        self.size = self.root.count();
+        self.size
+    }
+
+    /// Panic with a description of the first broken invariant of the tree, if any:
+    /// keys must be strictly increasing in order, `len` must match the number of
+    /// nodes, and the tree must be balanced with correct stored heights and counts.
+    #[cfg(any(test, debug_assertions))]
+    pub fn assert_valid(&self) {
+        let mut keys = self.keys();
+        if let Some(mut previous) = keys.next() {
+            for (i, key) in keys.enumerate() {
+                assert!(previous < key, "keys {i} and {} are out of order", i + 1);
+                previous = key;
+            }
+        }
+        let counted = self.root.count();
+        assert_eq!(
+            counted, self.size,
+            "the tree has {counted} nodes but the map has length {}",
+            self.size
+        );
+        self.root.check_shape();
+    }
+
+    /// The keys of the nodes visited while searching for `key`, from the root.
+    #[cfg(test)]
+    fn search_path(&self, key: &K) -> Vec<&K> {
+        let mut path = Vec::new();
+        let mut tree = &self.root;
+        while let BinaryTreeNode::Node(node) = tree {
+            path.push(&node.key);
+            tree = match key.cmp(&node.key) {
+                Ordering::Less => &node.left,
+                Ordering::Greater => &node.right,
+                Ordering::Equal => break,
+            };
+        }
+        path
+    }
+
+    /// Number of nodes on the longest root-to-leaf path, 0 for an empty map.
+    pub fn height(&self) -> usize {
+        self.root.height()
+    }
+
+    /// Number of nodes on the path from the root to `key`, so the root has depth 1.
+    pub fn depth_of(&self, key: &K) -> Option<usize> {
+        self.root.depth_of(key)
+    }
+
+    /// Compute height, size, depth and balance information in a single traversal.
+    pub fn shape_stats(&self) -> ShapeStats {
+        let (height, min_depth, leaves, leaf_depth_sum, balanced) = self.root.shape();
+        let average_leaf_depth = if leaves == 0 {
+            0.0
+        } else {
+            leaf_depth_sum as f64 / leaves as f64
+        };
+        ShapeStats {
+            height,
+            size: self.size,
+            min_depth,
+            average_leaf_depth,
+            balanced,
+        }
+    }
+}
+
+impl<K: Ord, V> BinaryTreeMap<K, V> {
+    /// Get the entry for `key`, to inspect or update it in place.
+    ///
+    /// Keys are only compared here: the entry records the path found, which
+    /// later operations on it follow instead of searching for the key again.
+    pub fn entry(&mut self, key: K) -> Entry<'_, K, V> {
+        let root = &mut self.root;
+        let size = &mut self.size;
+        match root.path_to(&key) {
+            Ok(path) => Entry::Occupied(OccupiedEntry { root, path, size }),
+            Err(path) => Entry::Vacant(VacantEntry {
+                key,
+                root,
+                path,
+                size,
+            }),
+        }
+    }
+
+    /// Get a mutable reference to the value of `key`, inserting `default` first if it is absent.
+    pub fn get_or_insert(&mut self, key: K, default: V) -> &mut V {
+        self.entry(key).or_insert(default)
+    }
+
+    /// Get a mutable reference to the value of `key`, inserting the result of
+    /// `f` first if it is absent. `f` is only called if needed.
+    pub fn get_or_insert_with<F: FnOnce() -> V>(&mut self, key: K, f: F) -> &mut V {
+        self.entry(key).or_insert_with(f)
+    }
+
+    /// Insert `f(&key)` for each of `keys` that is not in the map yet,
+    /// leaving existing values untouched.
+    pub fn upsert_range<F: FnMut(&K) -> V>(&mut self, keys: impl IntoIterator<Item = K>, mut f: F) {
+        for key in keys {
+            if let Entry::Vacant(entry) = self.entry(key) {
+                let value = f(entry.key());
+                entry.insert(value);
+            }
+        }
+    }
+}
+
+/// A view into a single entry of a [`BinaryTreeMap`], see [`BinaryTreeMap::entry`].
+pub enum Entry<'a, K, V> {
+    Occupied(OccupiedEntry<'a, K, V>),
+    Vacant(VacantEntry<'a, K, V>),
+}
+
+/// An entry whose key is in the map.
+///
+/// Accessing the entry walks down the recorded path again, without comparing
+/// keys. It cannot keep a reference to the node instead, since that would
+/// alias the borrow of the root that [`OccupiedEntry::remove_entry`] needs to
+/// rebalance the tree.
+pub struct OccupiedEntry<'a, K, V> {
+    root: &'a mut BinaryTreeNode<K, V>,
+    /// Path from the root to the node holding the entry.
+    path: Vec<Ordering>,
+    size: &'a mut usize,
+}
+
+/// An entry whose key is not in the map.
+pub struct VacantEntry<'a, K, V> {
+    key: K,
+    root: &'a mut BinaryTreeNode<K, V>,
+    /// Path from the root to the empty slot where the key belongs.
+    path: Vec<Ordering>,
+    size: &'a mut usize,
+}
+
+impl<'a, K: Ord, V> Entry<'a, K, V> {
+    /// Insert `default` if the entry is vacant, and return a reference to its value.
+    pub fn or_insert(self, default: V) -> &'a mut V {
+        match self {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(default),
+        }
+    }
+
+    /// Insert the result of `default` if the entry is vacant, and return a
+    /// reference to its value. `default` is only called if needed.
+    pub fn or_insert_with<F: FnOnce() -> V>(self, default: F) -> &'a mut V {
+        match self {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(default()),
+        }
+    }
+
+    /// Call `f` on the value of the entry if it is occupied.
+    pub fn and_modify<F: FnOnce(&mut V)>(mut self, f: F) -> Self {
+        if let Entry::Occupied(entry) = &mut self {
+            f(entry.get_mut());
+        }
+        self
+    }
+
+    /// The key of the entry, whether it is occupied or vacant.
+    pub fn key(&self) -> &K {
+        match self {
+            Entry::Occupied(entry) => entry.key(),
+            Entry::Vacant(entry) => entry.key(),
+        }
+    }
+
+    /// Set the value of the entry, inserting it if it is vacant,
+    /// and return a handle to the now occupied entry.
+    pub fn insert(self, value: V) -> OccupiedEntry<'a, K, V> {
+        match self {
+            Entry::Occupied(mut entry) => {
+                entry.insert(value);
+                entry
+            }
+            Entry::Vacant(entry) => entry.insert_entry(value),
+        }
+    }
+}
+
+impl<'a, K: Ord, V> OccupiedEntry<'a, K, V> {
+    fn node(&self) -> &Node<K, V> {
+        self.root.node_at(self.path.iter().copied())
+    }
+
+    fn node_mut(&mut self) -> &mut Node<K, V> {
+        self.root.node_at_mut(self.path.iter().copied())
+    }
+
+    pub fn key(&self) -> &K {
+        &self.node().key
+    }
+
+    pub fn get(&self) -> &V {
+        &self.node().value
+    }
+
+    pub fn get_mut(&mut self) -> &mut V {
+        &mut self.node_mut().value
+    }
+
+    /// Convert the entry into a reference to its value, with the lifetime of the map borrow.
+    pub fn into_mut(self) -> &'a mut V {
+        &mut self.root.node_at_mut(self.path).value
+    }
+
+    /// Set the value of the entry, returning the previous one.
+    pub fn insert(&mut self, value: V) -> V {
+        std::mem::replace(self.get_mut(), value)
+    }
+
+    /// Remove the entry from the map, returning its value.
+    pub fn remove(self) -> V {
+        self.remove_entry().1
+    }
+
+    /// Remove the entry from the map, returning its key and value.
+    pub fn remove_entry(self) -> (K, V) {
+        *self.size -= 1;
+        self.root.remove_at(&self.path)
+    }
+}
+
+impl<'a, K: Ord, V> VacantEntry<'a, K, V> {
+    pub fn key(&self) -> &K {
+        &self.key
+    }
+
+    /// Insert `value` for the key of the entry, returning a reference to it.
+    ///
+    /// The reference points into the new node, which stays in place for as
+    /// long as the map is borrowed.
+    pub fn insert(self, value: V) -> &'a mut V {
+        self.insert_entry(value).into_mut()
+    }
+
+    /// Insert `value` for the key of the entry, returning the occupied entry.
+    pub fn insert_entry(self, value: V) -> OccupiedEntry<'a, K, V> {
+        let path = self.root.insert_at(&self.path, self.key, value);
+        *self.size += 1;
+        OccupiedEntry {
+            root: self.root,
+            path: path.into(),
+            size: self.size,
+        }
+    }
+}
+
+impl<K: Ord, V> FromIterator<(K, V)> for BinaryTreeMap<K, V> {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        let mut map = BinaryTreeMap::new();
+        map.extend(iter);
+        map
+    }
+}
+
+/// Insert each pair in turn, later values replacing earlier ones for the same key.
+impl<K: Ord, V> Extend<(K, V)> for BinaryTreeMap<K, V> {
+    fn extend<I: IntoIterator<Item = (K, V)>>(&mut self, iter: I) {
+        for (key, value) in iter {
+            self.insert(key, value);
+        }
     }
+}
+
+impl<K: Ord, V> Index<&K> for BinaryTreeMap<K, V> {
+    type Output = V;
 
-    pub fn get(&self, key: &K) -> Option<&V> {
-        todo!("Retrieve the value associated to a key if it exists")
+    /// Get the value of `key`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `key` is not in the map.
+    fn index(&self, key: &K) -> &V {
+        self.get(key).expect("key not found in BinaryTreeMap")
     }
+}
 
-    pub fn contains(&self, key: &K) -> bool {
-        todo!("Whether the map contains the given key")
+impl<K: Ord, V> From<BinaryTreeMap<K, V>> for BTreeMap<K, V> {
+    fn from(map: BinaryTreeMap<K, V>) -> Self {
+        map.into_iter().collect()
     }
+}
 
-    pub fn remove(&mut self, key: &K) -> Option<V> {
-        todo!("If there is a value with the given key, remove it from the map and return it")
+/// Build a balanced map directly, since the entries of a `BTreeMap` are already sorted.
+impl<K: Ord, V> From<BTreeMap<K, V>> for BinaryTreeMap<K, V> {
+    fn from(map: BTreeMap<K, V>) -> Self {
+        Self::from_sorted(map.into_iter().collect())
     }
 }
 
 /// Create an iterator over the (key, value) pairs of the map,
 /// ordered by key.
-///
-/// Fix this definition !
-/// It should consume the map and return owned pairs, not references!
 impl<K, V> IntoIterator for BinaryTreeMap<K, V> {
     type Item = (K, V);
 
     type IntoIter = BinaryTreeMapIntoIterator<K, V>;
 
     fn into_iter(self) -> Self::IntoIter {
-        todo!()
+        BinaryTreeMapIntoIterator {
+            pending: VecDeque::from([Pending::Tree(self.root)]),
+            remaining: self.size,
+        }
     }
 }
 
+/// A part of the map not yet yielded by the consuming iterator.
+enum Pending<K, V> {
+    Entry(K, V),
+    Tree(BinaryTreeNode<K, V>),
+}
+
 pub struct BinaryTreeMapIntoIterator<K, V> {
-    _marker: (K, V),
+    /// The remaining entries, in ascending key order once the trees are expanded.
+    pending: VecDeque<Pending<K, V>>,
+    /// Number of entries not yielded yet.
+    remaining: usize,
 }
 
 impl<K, V> Iterator for BinaryTreeMapIntoIterator<K, V> {
     type Item = (K, V);
 
     fn next(&mut self) -> Option<Self::Item> {
-        todo!()
+        loop {
+            match self.pending.pop_front()? {
+                Pending::Entry(key, value) => {
+                    self.remaining -= 1;
+                    return Some((key, value));
+                }
+                Pending::Tree(BinaryTreeNode::Leaf) => {}
+                Pending::Tree(BinaryTreeNode::Node(node)) => {
+                    let Node {
+                        key,
+                        value,
+                        left,
+                        right,
+                        ..
+                    } = *node;
+                    self.pending.push_front(Pending::Tree(right));
+                    self.pending.push_front(Pending::Entry(key, value));
+                    self.pending.push_front(Pending::Tree(left));
+                }
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+impl<K, V> DoubleEndedIterator for BinaryTreeMapIntoIterator<K, V> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.pending.pop_back()? {
+                Pending::Entry(key, value) => {
+                    self.remaining -= 1;
+                    return Some((key, value));
+                }
+                Pending::Tree(BinaryTreeNode::Leaf) => {}
+                Pending::Tree(BinaryTreeNode::Node(node)) => {
+                    let Node {
+                        key,
+                        value,
+                        left,
+                        right,
+                        ..
+                    } = *node;
+                    self.pending.push_back(Pending::Tree(left));
+                    self.pending.push_back(Pending::Entry(key, value));
+                    self.pending.push_back(Pending::Tree(right));
+                }
+            }
+        }
+    }
+}
 
-    #[test]
-    fn map_insert_contains() {
-        let mut map = BinaryTreeMap::new();
+impl<K, V> ExactSizeIterator for BinaryTreeMapIntoIterator<K, V> {}
 
-        map.insert(1, (10, 12));
-        map.insert(4, (23, 17));
-        map.insert(2, (1, 1));
+impl<K, V> BinaryTreeMap<K, V> {
+    /// Iterate over the (key, value) pairs of the map by reference, ordered by key.
+    pub fn iter(&self) -> BinaryTreeMapIter<'_, K, V> {
+        let mut iter = BinaryTreeMapIter {
+            front: Vec::new(),
+            back: Vec::new(),
+            remaining: self.size,
+        };
+        iter.push_left_spine(&self.root);
+        iter.push_right_spine(&self.root);
+        iter
+    }
 
-        assert_eq!(map.get(&0), None);
-        assert_eq!(map.get(&1), Some(&(10, 12)));
-        assert_eq!(map.get(&2), Some(&(1, 1)));
-        assert_eq!(map.get(&3), None);
-        assert_eq!(map.get(&4), Some(&(23, 17)));
+    /// Iterate over the (key, value) pairs of the map, ordered by key,
+    /// with mutable references to the values.
+    pub fn iter_mut(&mut self) -> BinaryTreeMapIterMut<'_, K, V> {
+        let mut iter = BinaryTreeMapIterMut {
+            stack: Vec::new(),
+            remaining: self.size,
+        };
+        iter.push_left_spine(&mut self.root);
+        iter
+    }
 
-        assert_eq!(map.insert(2, (15, 0)), Some((1, 1)));
+    /// Iterate over the keys of the map, in ascending order.
+    pub fn keys(&self) -> impl Iterator<Item = &K> {
+        self.iter().map(|(k, _)| k)
     }
 
-    #[test]
-    fn map_remove() {
-        let mut map = BinaryTreeMap::new();
+    /// Iterate over the values of the map, ordered by key.
+    pub fn values(&self) -> impl Iterator<Item = &V> {
+        self.iter().map(|(_, v)| v)
+    }
+
+    /// Iterate over mutable references to the values of the map, ordered by key.
+    pub fn values_mut(&mut self) -> impl Iterator<Item = &mut V> {
+        self.iter_mut().map(|(_, v)| v)
+    }
+
+    /// Consume the map, yielding its keys in ascending order.
+    pub fn into_keys(self) -> impl Iterator<Item = K> {
+        self.into_iter().map(|(k, _)| k)
+    }
+
+    /// Consume the map, yielding its values ordered by key.
+    pub fn into_values(self) -> impl Iterator<Item = V> {
+        self.into_iter().map(|(_, v)| v)
+    }
+}
+
+impl<K: fmt::Debug, V: fmt::Debug> fmt::Debug for BinaryTreeMap<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+/// Maps are equal when they hold the same entries, whatever the shape of their trees.
+impl<K: PartialEq, V: PartialEq> PartialEq for BinaryTreeMap<K, V> {
+    fn eq(&self, other: &Self) -> bool {
+        self.size == other.size && self.iter().eq(other.iter())
+    }
+}
+
+impl<K: Eq, V: Eq> Eq for BinaryTreeMap<K, V> {}
+
+/// Serialize as a map, with entries in ascending key order.
+#[cfg(feature = "serde")]
+impl<K: serde::Serialize, V: serde::Serialize> serde::Serialize for BinaryTreeMap<K, V> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_map(self.iter())
+    }
+}
+
+/// Deserialize from a map, inserting the entries one by one.
+#[cfg(feature = "serde")]
+impl<'de, K, V> serde::Deserialize<'de> for BinaryTreeMap<K, V>
+where
+    K: Ord + serde::Deserialize<'de>,
+    V: serde::Deserialize<'de>,
+{
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct MapVisitor<K, V>(std::marker::PhantomData<(K, V)>);
+
+        impl<'de, K, V> serde::de::Visitor<'de> for MapVisitor<K, V>
+        where
+            K: Ord + serde::Deserialize<'de>,
+            V: serde::Deserialize<'de>,
+        {
+            type Value = BinaryTreeMap<K, V>;
+
+            fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str("a map")
+            }
+
+            fn visit_map<A: serde::de::MapAccess<'de>>(
+                self,
+                mut access: A,
+            ) -> Result<Self::Value, A::Error> {
+                let mut map = BinaryTreeMap::new();
+                while let Some((key, value)) = access.next_entry()? {
+                    map.insert(key, value);
+                }
+                Ok(map)
+            }
+        }
+
+        deserializer.deserialize_map(MapVisitor(std::marker::PhantomData))
+    }
+}
+
+impl<'a, K, V> IntoIterator for &'a BinaryTreeMap<K, V> {
+    type Item = (&'a K, &'a V);
+
+    type IntoIter = BinaryTreeMapIter<'a, K, V>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+pub struct BinaryTreeMapIter<'a, K, V> {
+    /// Nodes whose left subtree has already been visited from the front.
+    front: Vec<&'a Node<K, V>>,
+    /// Nodes whose right subtree has already been visited from the back.
+    back: Vec<&'a Node<K, V>>,
+    /// Number of entries not yielded yet from either end, so that the two
+    /// ends stop when they meet.
+    remaining: usize,
+}
+
+impl<'a, K, V> BinaryTreeMapIter<'a, K, V> {
+    fn push_left_spine(&mut self, mut tree: &'a BinaryTreeNode<K, V>) {
+        while let BinaryTreeNode::Node(node) = tree {
+            self.front.push(node);
+            tree = &node.left;
+        }
+    }
+
+    fn push_right_spine(&mut self, mut tree: &'a BinaryTreeNode<K, V>) {
+        while let BinaryTreeNode::Node(node) = tree {
+            self.back.push(node);
+            tree = &node.right;
+        }
+    }
+}
+
+impl<'a, K, V> Iterator for BinaryTreeMapIter<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        let node = self.front.pop()?;
+        self.push_left_spine(&node.right);
+        self.remaining -= 1;
+        Some((&node.key, &node.value))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<'a, K, V> DoubleEndedIterator for BinaryTreeMapIter<'a, K, V> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        let node = self.back.pop()?;
+        self.push_right_spine(&node.left);
+        self.remaining -= 1;
+        Some((&node.key, &node.value))
+    }
+}
+
+impl<'a, K, V> ExactSizeIterator for BinaryTreeMapIter<'a, K, V> {}
+
+struct BinaryTreeMapRange<'a, K, V, R> {
+    /// Nodes not below the range whose left subtree has already been visited.
+    stack: Vec<&'a Node<K, V>>,
+    range: R,
+}
+
+impl<'a, K: Ord, V, R: RangeBounds<K>> BinaryTreeMapRange<'a, K, V, R> {
+    fn push_left_spine(&mut self, mut tree: &'a BinaryTreeNode<K, V>) {
+        while let BinaryTreeNode::Node(node) = tree {
+            if after_start(&self.range, &node.key) {
+                self.stack.push(node);
+                tree = &node.left;
+            } else {
+                tree = &node.right;
+            }
+        }
+    }
+}
+
+impl<'a, K: Ord, V, R: RangeBounds<K>> Iterator for BinaryTreeMapRange<'a, K, V, R> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let node = self.stack.pop()?;
+        if !self.range.contains(&node.key) {
+            // Every remaining node is past the end of the range.
+            self.stack.clear();
+            return None;
+        }
+        self.push_left_spine(&node.right);
+        Some((&node.key, &node.value))
+    }
+}
+
+impl<'a, K, V> IntoIterator for &'a mut BinaryTreeMap<K, V> {
+    type Item = (&'a K, &'a mut V);
+
+    type IntoIter = BinaryTreeMapIterMut<'a, K, V>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter_mut()
+    }
+}
+
+pub struct BinaryTreeMapIterMut<'a, K, V> {
+    /// Entries whose left subtree has already been visited, with their right subtree.
+    stack: Vec<(&'a K, &'a mut V, &'a mut BinaryTreeNode<K, V>)>,
+    /// Number of entries not yielded yet.
+    remaining: usize,
+}
+
+impl<'a, K, V> BinaryTreeMapIterMut<'a, K, V> {
+    fn push_left_spine(&mut self, mut tree: &'a mut BinaryTreeNode<K, V>) {
+        while let BinaryTreeNode::Node(node) = tree {
+            let Node {
+                key,
+                value,
+                left,
+                right,
+                ..
+            } = &mut **node;
+            self.stack.push((key, value, right));
+            tree = left;
+        }
+    }
+}
+
+impl<'a, K, V> Iterator for BinaryTreeMapIterMut<'a, K, V> {
+    type Item = (&'a K, &'a mut V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (key, value, right) = self.stack.pop()?;
+        self.push_left_spine(right);
+        self.remaining -= 1;
+        Some((key, value))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<'a, K, V> ExactSizeIterator for BinaryTreeMapIterMut<'a, K, V> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn map_insert_contains() {
+        let mut map = BinaryTreeMap::new();
+
+        map.insert(1, (10, 12));
+        map.insert(4, (23, 17));
+        map.insert(2, (1, 1));
+
+        assert_eq!(map.get(&0), None);
+        assert_eq!(map.get(&1), Some(&(10, 12)));
+        assert_eq!(map.get(&2), Some(&(1, 1)));
+        assert_eq!(map.get(&3), None);
+        assert_eq!(map.get(&4), Some(&(23, 17)));
+
+        assert_eq!(map.insert(2, (15, 0)), Some((1, 1)));
+    }
+
+    #[test]
+    fn map_remove() {
+        let mut map = BinaryTreeMap::new();
 
         map.insert(1, (10, 12));
         map.insert(4, (23, 17));
@@ -120,4 +1712,1078 @@ mod tests {
         assert_eq!(iter.next(), Some((5, "you?")));
         assert_eq!(iter.next(), None);
     }
+
+    /// A map holding `1..=n` as a chain of right children, which `insert`
+    /// would never build since it keeps the tree balanced.
+    fn right_chain(n: i32) -> BinaryTreeMap<i32, String> {
+        let mut root = BinaryTreeNode::Leaf;
+        for k in (1..=n).rev() {
+            root = BinaryTreeNode::Node(Box::new(Node::new(
+                k,
+                k.to_string(),
+                BinaryTreeNode::Leaf,
+                root,
+            )));
+        }
+        BinaryTreeMap {
+            root,
+            size: n as usize,
+        }
+    }
+
+    #[test]
+    fn shape_stats_degenerate() {
+        let map = right_chain(5);
+
+        let stats = map.shape_stats();
+        assert_eq!(stats.height, 5);
+        assert_eq!(stats.size, 5);
+        assert_eq!(stats.min_depth, 5);
+        assert_eq!(stats.average_leaf_depth, 5.0);
+        assert!(!stats.balanced);
+    }
+
+    #[test]
+    fn shape_stats_balanced() {
+        let mut map = BinaryTreeMap::new();
+        for k in [4, 2, 6, 1, 3, 5, 7] {
+            map.insert(k, ());
+        }
+
+        let stats = map.shape_stats();
+        assert_eq!(stats.height, 3);
+        assert_eq!(stats.size, 7);
+        assert_eq!(stats.min_depth, 3);
+        assert_eq!(stats.average_leaf_depth, 3.0);
+        assert!(stats.balanced);
+
+        map.insert(8, ());
+        let stats = map.shape_stats();
+        assert_eq!(stats.height, 4);
+        assert_eq!(stats.min_depth, 3);
+        assert_eq!(stats.average_leaf_depth, 13.0 / 4.0);
+        assert!(stats.balanced);
+    }
+
+    #[test]
+    fn append_sorted_greater() {
+        let mut map = BinaryTreeMap::new();
+        map.append_sorted_greater((1..=100).map(|k| (k, k * 2)));
+        assert_eq!(map.len(), 100);
+        assert_eq!(map.shape_stats().height, 7);
+
+        map.append_sorted_greater((101..=200).map(|k| (k, k * 2)));
+        assert_eq!(map.len(), 200);
+        assert!(map.shape_stats().height <= 2 * 8);
+        for k in 1..=200 {
+            assert_eq!(map.get(&k), Some(&(k * 2)));
+        }
+        assert!(map.into_iter().map(|(k, _)| k).eq(1..=200));
+    }
+
+    #[test]
+    fn append_sorted_greater_grafts_any_size() {
+        for (old, new) in [(1, 1000), (1000, 1), (3, 60), (60, 3), (500, 500)] {
+            let mut map: BinaryTreeMap<_, _> = (0..old).map(|k| (k, k)).collect();
+            map.append_sorted_greater((old..old + new).map(|k| (k, k)));
+            map.assert_valid();
+            assert_eq!(map.len(), old + new);
+            assert!(map.into_iter().map(|(k, _)| k).eq(0..old + new));
+        }
+    }
+
+    #[test]
+    fn entry_insert_then_remove() {
+        let mut map = BinaryTreeMap::new();
+        map.insert(2, "two");
+        map.insert(1, "one");
+
+        let mut entry = map.entry(3).insert("three");
+        assert_eq!(entry.get(), &"three");
+        *entry.get_mut() = "THREE";
+        assert_eq!(map.len(), 3);
+        assert_eq!(map.get(&3), Some(&"THREE"));
+
+        let entry = map.entry(2).insert("deux");
+        assert_eq!(entry.remove(), "deux");
+        assert_eq!(map.len(), 2);
+        assert_eq!(map.get(&2), None);
+        assert!(map.into_iter().eq([(1, "one"), (3, "THREE")]));
+    }
+
+    #[test]
+    fn range_sum() {
+        let mut map = BinaryTreeMap::new();
+        for k in [5, 2, 8, 1, 3, 7, 9, 4, 6, 0] {
+            map.insert(k, k * 10);
+        }
+
+        let expected: i32 = (3..=7).map(|k| k * 10).sum();
+        assert_eq!(map.range_sum(3..=7), expected);
+        assert_eq!(map.range_sum(3..7), expected - 70);
+        assert_eq!(map.range_sum(..), 450);
+        assert_eq!(map.range_sum(8..), 170);
+        assert_eq!(map.range_sum(20..30), 0);
+    }
+
+    #[test]
+    fn entry_key() {
+        let mut map = BinaryTreeMap::new();
+        map.insert(1, "one");
+
+        let entry = map.entry(1);
+        assert!(matches!(entry, Entry::Occupied(_)));
+        assert_eq!(entry.key(), &1);
+
+        let entry = map.entry(2);
+        assert!(matches!(entry, Entry::Vacant(_)));
+        assert_eq!(entry.key(), &2);
+        assert_eq!(map.len(), 1);
+    }
+
+    #[test]
+    fn get_or_insert() {
+        let mut map = BinaryTreeMap::new();
+        map.insert(1, 10);
+
+        *map.get_or_insert(1, 0) += 1;
+        assert_eq!(map.len(), 1);
+        assert_eq!(map.get(&1), Some(&11));
+
+        *map.get_or_insert(2, 20) += 1;
+        assert_eq!(map.len(), 2);
+        assert_eq!(map.get(&2), Some(&21));
+    }
+
+    #[test]
+    fn flat_map_values() {
+        let mut map = BinaryTreeMap::new();
+        map.insert(2, "b");
+        map.insert(1, "a");
+        map.insert(3, "c");
+
+        let split = map.flat_map_values(|k, v| [(k, v), (k + 100, v)]);
+        assert_eq!(split.len(), 6);
+        assert!(split.into_iter().eq([
+            (1, "a"),
+            (2, "b"),
+            (3, "c"),
+            (101, "a"),
+            (102, "b"),
+            (103, "c"),
+        ]));
+
+        let mut map = BinaryTreeMap::new();
+        map.insert(1, 10);
+        map.insert(2, 20);
+        let merged = map.flat_map_values(|_, v| Some((0, v)));
+        assert_eq!(merged.len(), 1);
+        assert_eq!(merged.get(&0), Some(&20));
+    }
+
+    #[test]
+    fn recount_size() {
+        let mut map = BinaryTreeMap::new();
+        for k in [3, 1, 4, 5, 9, 2, 6] {
+            map.insert(k, ());
+        }
+        assert_eq!(map.recount_size(), 7);
+
+        map.size = 42;
+        assert_eq!(map.recount_size(), 7);
+        assert_eq!(map.len(), 7);
+    }
+
+    #[test]
+    fn split_at_index() {
+        let mut map = BinaryTreeMap::new();
+        for k in 1..=10 {
+            map.insert(k, k.to_string());
+        }
+
+        let (low, high) = map.split_at_index(3);
+        assert_eq!(low.len(), 3);
+        assert_eq!(high.len(), 7);
+        assert!(low.shape_stats().balanced && high.shape_stats().balanced);
+        assert!(low.into_iter().map(|(k, _)| k).eq(1..=3));
+        assert!(high.into_iter().eq((4..=10).map(|k| (k, k.to_string()))));
+    }
+
+    #[test]
+    fn vacant_insert_reference() {
+        let mut map = BinaryTreeMap::new();
+        for k in [50, 25, 75, 60, 90] {
+            map.insert(k, k);
+        }
+
+        let Entry::Vacant(entry) = map.entry(70) else {
+            panic!("70 should be vacant");
+        };
+        let value = entry.insert(0);
+        *value += 7;
+        *value *= 10;
+
+        assert_eq!(map.get(&70), Some(&70));
+        assert_eq!(map.get(&60), Some(&60));
+        assert_eq!(map.len(), 6);
+    }
+
+    #[test]
+    fn upsert_range() {
+        let mut map = BinaryTreeMap::new();
+        map.insert(2, 0);
+        map.insert(4, 0);
+
+        let mut calls = 0;
+        map.upsert_range(1..=5, |k| {
+            calls += 1;
+            k * 10
+        });
+        assert_eq!(calls, 3);
+        assert_eq!(map.len(), 5);
+        assert!(map
+            .into_iter()
+            .eq([(1, 10), (2, 0), (3, 30), (4, 0), (5, 50)]));
+    }
+
+    #[test]
+    fn search_path() {
+        let mut map = BinaryTreeMap::new();
+        for k in [50, 25, 75, 10, 30, 60] {
+            map.insert(k, ());
+        }
+
+        assert_eq!(map.search_path(&30), vec![&50, &25, &30]);
+        assert_eq!(map.search_path(&50), vec![&50]);
+        assert_eq!(map.search_path(&27), vec![&50, &25, &30]);
+        assert_eq!(map.search_path(&99), vec![&50, &75]);
+        assert!(BinaryTreeMap::<i32, ()>::new().search_path(&1).is_empty());
+    }
+
+    #[test]
+    fn drain() {
+        let mut map = BinaryTreeMap::new();
+        for k in [3, 1, 2] {
+            map.insert(k, k * 10);
+        }
+
+        let drained: Vec<_> = map.drain().collect();
+        assert_eq!(drained, vec![(1, 10), (2, 20), (3, 30)]);
+        assert!(map.is_empty());
+        assert_eq!(map.get(&1), None);
+
+        map.insert(7, 70);
+        assert_eq!(map.len(), 1);
+        assert_eq!(map.get(&7), Some(&70));
+    }
+
+    #[test]
+    fn drain_dropped_early() {
+        let mut map: BinaryTreeMap<_, _> = (0..10).map(|k| (k, k.to_string())).collect();
+        let mut drain = map.drain();
+        let first_half: Vec<_> = drain.by_ref().take(5).map(|(k, _)| k).collect();
+        assert_eq!(first_half, [0, 1, 2, 3, 4]);
+        drop(drain);
+
+        assert!(map.is_empty());
+        assert_eq!(map.iter().count(), 0);
+        assert_eq!(map.get(&7), None);
+    }
+
+    #[test]
+    fn retain_returning() {
+        let mut map = BinaryTreeMap::new();
+        for k in 1..=10 {
+            map.insert(k, k);
+        }
+
+        let removed = map.retain_returning(|k, v| {
+            *v *= 2;
+            k % 2 == 0
+        });
+        assert_eq!(removed, 5);
+        assert_eq!(map.len(), 5);
+        assert!(map.into_iter().eq((1..=5).map(|k| (2 * k, 4 * k))));
+    }
+
+    #[test]
+    fn get_many() {
+        let mut map = BinaryTreeMap::new();
+        map.insert(String::from("apple"), 1);
+        map.insert(String::from("pear"), 2);
+        map.insert(String::from("fig"), 3);
+
+        assert_eq!(
+            map.get_many(&["fig", "kiwi", "apple", "fig"]),
+            vec![Some(&3), None, Some(&1), Some(&3)]
+        );
+        assert!(map.get_many::<str>(&[]).is_empty());
+    }
+
+    #[test]
+    fn map_iter() {
+        let mut map = BinaryTreeMap::new();
+        map.insert(3, "c");
+        map.insert(1, "a");
+        map.insert(4, "d");
+        map.insert(2, "b");
+
+        let pairs: Vec<_> = map.iter().collect();
+        assert_eq!(pairs, vec![(&1, &"a"), (&2, &"b"), (&3, &"c"), (&4, &"d")]);
+        assert!(map.iter().eq(map.iter()));
+
+        let mut keys = Vec::new();
+        for (k, _) in &map {
+            keys.push(*k);
+        }
+        assert_eq!(keys, vec![1, 2, 3, 4]);
+        assert_eq!(BinaryTreeMap::<i32, ()>::new().iter().next(), None);
+    }
+
+    #[test]
+    fn map_iter_mut() {
+        let mut map = BinaryTreeMap::new();
+        for k in [5, 2, 8, 1, 9, 3] {
+            map.insert(k, k * 10);
+        }
+
+        for (k, v) in map.iter_mut() {
+            *v += k;
+        }
+        for (_, v) in &mut map {
+            *v += 1;
+        }
+        let pairs: Vec<_> = map.iter().map(|(&k, &v)| (k, v)).collect();
+        assert_eq!(
+            pairs,
+            vec![(1, 12), (2, 23), (3, 34), (5, 56), (8, 89), (9, 100)]
+        );
+        assert_eq!(map.len(), 6);
+    }
+
+    #[test]
+    fn keys_and_values() {
+        let mut map = BinaryTreeMap::new();
+        assert_eq!(map.keys().next(), None);
+        assert_eq!(map.values().next(), None);
+
+        for (k, v) in [(2, "b"), (3, "c"), (1, "a")] {
+            map.insert(k, v.to_string());
+        }
+        assert_eq!(map.keys().collect::<Vec<_>>(), vec![&1, &2, &3]);
+        assert_eq!(map.values().collect::<Vec<_>>(), vec!["a", "b", "c"]);
+
+        for v in map.values_mut() {
+            v.push('!');
+        }
+        assert_eq!(map.get(&2).map(String::as_str), Some("b!"));
+    }
+
+    #[test]
+    fn map_get_mut() {
+        let mut map = BinaryTreeMap::new();
+        map.insert(2, vec![1]);
+        map.insert(1, vec![]);
+        map.insert(3, vec![2, 3]);
+
+        map.get_mut(&3).unwrap().push(4);
+        assert_eq!(map.get(&3), Some(&vec![2, 3, 4]));
+        assert_eq!(map.get_mut(&4), None);
+        assert_eq!(map.len(), 3);
+    }
+
+    #[test]
+    fn entry_word_count() {
+        let mut map = BinaryTreeMap::new();
+        for w in "a rose is a rose is a rose".split(' ') {
+            *map.entry(w).or_insert(0) += 1;
+        }
+        assert_eq!(map.len(), 3);
+        assert!(map.iter().eq([(&"a", &3), (&"is", &2), (&"rose", &3)]));
+    }
+
+    #[test]
+    fn entry_compares_keys_once() {
+        use std::cell::Cell;
+
+        thread_local! {
+            static COMPARISONS: Cell<usize> = const { Cell::new(0) };
+        }
+
+        #[derive(PartialEq, Eq)]
+        struct Counted(i32);
+
+        impl PartialOrd for Counted {
+            fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+                Some(self.cmp(other))
+            }
+        }
+
+        impl Ord for Counted {
+            fn cmp(&self, other: &Self) -> Ordering {
+                COMPARISONS.with(|c| c.set(c.get() + 1));
+                self.0.cmp(&other.0)
+            }
+        }
+
+        let mut map: BinaryTreeMap<_, _> = (0..1000).map(|k| (Counted(2 * k), k)).collect();
+        for k in [-1, 1, 999, 2001] {
+            COMPARISONS.with(|c| c.set(0));
+            *map.entry(Counted(k)).or_insert(0) += 1;
+            let depth = map.depth_of(&Counted(k)).unwrap();
+            COMPARISONS.with(|c| c.set(c.get() - depth));
+            // One comparison per node above the slot of the new key, none
+            // while inserting it there.
+            assert!(COMPARISONS.with(Cell::get) <= map.height());
+            map.assert_valid();
+        }
+
+        // Updating an existing key compares it once per node down to its own,
+        // as does measuring its depth.
+        COMPARISONS.with(|c| c.set(0));
+        *map.entry(Counted(1000)).or_insert(0) += 1;
+        let depth = map.depth_of(&Counted(1000)).unwrap();
+        assert_eq!(COMPARISONS.with(Cell::get), 2 * depth);
+        assert_eq!(map[&Counted(1000)], 501);
+    }
+
+    #[test]
+    fn entry_or_insert_with_and_modify() {
+        let mut map = BinaryTreeMap::new();
+        map.entry(1).or_insert_with(|| 10);
+        map.entry(1)
+            .or_insert_with(|| panic!("1 is already present"));
+        assert_eq!(map.get(&1), Some(&10));
+
+        map.entry(1).and_modify(|v| *v += 1).or_insert(0);
+        map.entry(2).and_modify(|v| *v += 1).or_insert(0);
+        assert_eq!(map.get(&1), Some(&11));
+        assert_eq!(map.get(&2), Some(&0));
+        assert_eq!(map.len(), 2);
+    }
+
+    #[test]
+    fn collect_and_extend() {
+        let pairs = vec![(3, "c"), (1, "a"), (3, "C"), (2, "b")];
+        let mut map: BinaryTreeMap<_, _> = pairs.into_iter().collect();
+        assert_eq!(map.len(), 3);
+        assert_eq!(map.get(&3), Some(&"C"));
+
+        map.extend([(4, "d"), (1, "A")]);
+        assert_eq!(map.len(), 4);
+        assert!(map.into_iter().eq([(1, "A"), (2, "b"), (3, "C"), (4, "d")]));
+    }
+
+    #[test]
+    fn clone_is_independent() {
+        let mut map: BinaryTreeMap<_, _> = [(4, "d"), (2, "b"), (6, "f"), (1, "a"), (5, "e")]
+            .into_iter()
+            .collect();
+        let snapshot = map.clone();
+
+        assert_eq!(snapshot.len(), map.len());
+        assert!(snapshot.iter().eq(map.iter()));
+        for k in map.keys() {
+            assert_eq!(snapshot.search_path(k), map.search_path(k));
+        }
+
+        map.insert(3, "c");
+        map.remove(&4);
+        *map.get_mut(&1).unwrap() = "A";
+        assert_eq!(snapshot.len(), 5);
+        assert!(snapshot
+            .into_iter()
+            .eq([(1, "a"), (2, "b"), (4, "d"), (5, "e"), (6, "f")]));
+    }
+
+    #[test]
+    fn debug_prints_entries_in_order() {
+        let mut map = BinaryTreeMap::new();
+        assert_eq!(format!("{map:?}"), "{}");
+        map.extend([(2, "b"), (3, "c"), (1, "a")]);
+        assert_eq!(format!("{map:?}"), r#"{1: "a", 2: "b", 3: "c"}"#);
+    }
+
+    #[test]
+    fn range_yields_keys_in_bounds() {
+        let map: BinaryTreeMap<u64, u64> = (0..100).map(|k| ((k * 37) % 100, k)).collect();
+        assert_eq!(map.range(10..20).count(), 10);
+        assert!(map.range(10..20).map(|(k, _)| *k).eq(10..20));
+        assert!(map.range(10..=20).map(|(k, _)| *k).eq(10..=20));
+        assert!(map.range(95..).map(|(k, _)| *k).eq(95..100));
+        assert!(map.range(..3).map(|(k, _)| *k).eq(0..3));
+        assert!(map
+            .range((Bound::Excluded(40), Bound::Excluded(43)))
+            .map(|(k, _)| *k)
+            .eq([41, 42]));
+        assert_eq!(map.range(200..300).count(), 0);
+        assert_eq!(map.range(..).count(), 100);
+    }
+
+    #[test]
+    fn first_and_last_key_value() {
+        let mut map = BinaryTreeMap::new();
+        assert_eq!(map.first_key_value(), None);
+        assert_eq!(map.last_key_value(), None);
+
+        map.insert(7, 'x');
+        assert_eq!(map.first_key_value(), Some((&7, &'x')));
+        assert_eq!(map.last_key_value(), Some((&7, &'x')));
+
+        let skewed = right_chain(10);
+        assert_eq!(skewed.height(), 10);
+        assert_eq!(skewed.first_key_value(), Some((&1, &"1".to_string())));
+        assert_eq!(skewed.last_key_value(), Some((&10, &"10".to_string())));
+    }
+
+    #[test]
+    fn pop_first_and_last() {
+        let mut map: BinaryTreeMap<_, _> = [5, 3, 8, 1, 4, 7, 9, 2, 6]
+            .into_iter()
+            .map(|k| (k, -k))
+            .collect();
+        assert_eq!(map.pop_last(), Some((9, -9)));
+        assert_eq!(map.pop_last(), Some((8, -8)));
+        assert_eq!(map.len(), 7);
+
+        let mut popped = Vec::new();
+        while let Some((k, v)) = map.pop_first() {
+            assert_eq!(v, -k);
+            assert!(map.keys().all(|other| *other > k));
+            popped.push(k);
+        }
+        assert_eq!(popped, [1, 2, 3, 4, 5, 6, 7]);
+        assert_eq!(map.len(), 0);
+        assert_eq!(map.pop_first(), None);
+        assert_eq!(map.pop_last(), None);
+    }
+
+    #[test]
+    fn floor_and_ceiling() {
+        let rates: BinaryTreeMap<_, _> = [(10, "a"), (20, "b"), (50, "c"), (100, "d")]
+            .into_iter()
+            .collect();
+        assert_eq!(rates.floor(&20), Some((&20, &"b")));
+        assert_eq!(rates.ceiling(&20), Some((&20, &"b")));
+        assert_eq!(rates.floor(&49), Some((&20, &"b")));
+        assert_eq!(rates.ceiling(&49), Some((&50, &"c")));
+        assert_eq!(rates.floor(&75), Some((&50, &"c")));
+        assert_eq!(rates.ceiling(&75), Some((&100, &"d")));
+        assert_eq!(rates.floor(&5), None);
+        assert_eq!(rates.ceiling(&5), Some((&10, &"a")));
+        assert_eq!(rates.floor(&500), Some((&100, &"d")));
+        assert_eq!(rates.ceiling(&500), None);
+        assert_eq!(BinaryTreeMap::<i32, i32>::new().floor(&0), None);
+    }
+
+    #[test]
+    fn walk_with_successor_and_predecessor() {
+        let keys = [40, 10, 70, 5, 20, 60, 90, 15];
+        let map: BinaryTreeMap<_, _> = keys.iter().map(|&k| (k, ())).collect();
+        let mut sorted = keys.to_vec();
+        sorted.sort();
+
+        let mut walked = vec![*map.first_key_value().unwrap().0];
+        while let Some(next) = map.successor(walked.last().unwrap()) {
+            walked.push(*next);
+        }
+        assert_eq!(walked, sorted);
+
+        let mut walked = vec![*map.last_key_value().unwrap().0];
+        while let Some(prev) = map.predecessor(walked.last().unwrap()) {
+            walked.push(*prev);
+        }
+        sorted.reverse();
+        assert_eq!(walked, sorted);
+
+        // Absent keys.
+        assert_eq!(map.successor(&50), Some(&60));
+        assert_eq!(map.predecessor(&50), Some(&40));
+        assert_eq!(map.predecessor(&0), None);
+        assert_eq!(map.successor(&100), None);
+    }
+
+    #[test]
+    fn height_and_depth_of() {
+        let chain = right_chain(7);
+        assert_eq!(chain.height(), 7);
+        assert_eq!(chain.depth_of(&1), Some(1));
+        assert_eq!(chain.depth_of(&7), Some(7));
+
+        // Sorted insertions are rebalanced as they go.
+        let sorted: BinaryTreeMap<_, _> = (1..=7).map(|k| (k, ())).collect();
+        assert_eq!(sorted.height(), 3);
+        assert_eq!(sorted.depth_of(&4), Some(1));
+        assert_eq!(sorted.depth_of(&7), Some(3));
+
+        let balanced: BinaryTreeMap<_, _> =
+            [4, 2, 6, 1, 3, 5, 7].map(|k| (k, ())).into_iter().collect();
+        assert_eq!(balanced.height(), 3);
+        assert_eq!(balanced.depth_of(&4), Some(1));
+        assert_eq!(balanced.depth_of(&6), Some(2));
+        assert_eq!(balanced.depth_of(&5), Some(3));
+        assert_eq!(balanced.depth_of(&8), None);
+
+        assert_eq!(BinaryTreeMap::<i32, ()>::new().height(), 0);
+    }
+
+    #[test]
+    fn rebalance_sorted_insertions() {
+        let mut map = right_chain(1000);
+        assert_eq!(map.height(), 1000);
+
+        map.rebalance();
+        // ceil(log2(1001))
+        assert_eq!(map.height(), 10);
+        assert!(map.shape_stats().balanced);
+        assert_eq!(map.len(), 1000);
+        assert!(map
+            .iter()
+            .map(|(k, v)| (*k, v.clone()))
+            .eq((1..=1000).map(|k| (k, k.to_string()))));
+    }
+
+    #[test]
+    fn ascending_insertions_stay_balanced() {
+        let n = 100_000;
+        let mut map = BinaryTreeMap::new();
+        for k in 0..n {
+            map.insert(k, ());
+        }
+        assert_eq!(map.len(), n);
+        assert!(map.height() as f64 <= 2.0 * (n as f64).log2());
+        assert!(map.keys().copied().eq(0..n));
+    }
+
+    #[test]
+    fn random_operations_match_btree_map() {
+        use rand::{rngs::StdRng, Rng, SeedableRng};
+
+        let mut rng = StdRng::seed_from_u64(266);
+        for _ in 0..20 {
+            let mut map = BinaryTreeMap::new();
+            let mut expected = BTreeMap::new();
+            for step in 0..500 {
+                let key = rng.gen_range(0..200);
+                if rng.gen_bool(0.3) {
+                    assert_eq!(map.insert(key, step), expected.insert(key, step));
+                } else if rng.gen_bool(0.5) {
+                    // The returned reference must follow the new node through rotations.
+                    let value = map.get_or_insert(key, step);
+                    assert_eq!(*value, *expected.entry(key).or_insert(step));
+                } else if rng.gen_bool(0.5) {
+                    assert_eq!(map.remove(&key), expected.remove(&key));
+                } else if let Entry::Occupied(entry) = map.entry(key) {
+                    assert_eq!(entry.remove(), expected.remove(&key).unwrap());
+                }
+
+                assert_eq!(map.len(), expected.len());
+                assert_eq!(map.recount_size(), expected.len());
+                assert_eq!(map.root.size(), expected.len());
+                map.assert_valid();
+                let stats = map.shape_stats();
+                assert!(stats.balanced);
+                assert_eq!(map.height(), stats.height);
+            }
+            assert!(map.into_iter().eq(expected));
+        }
+    }
+
+    #[test]
+    fn retain_even_keys() {
+        let mut map: BinaryTreeMap<_, _> = (0..20).map(|k| (k, k * 3)).collect();
+        map.retain(|k, v| {
+            *v += 1;
+            k % 2 == 0
+        });
+        assert_eq!(map.len(), 10);
+        assert!(map.shape_stats().balanced);
+        assert!(map
+            .into_iter()
+            .eq((0..20).step_by(2).map(|k| (k, k * 3 + 1))));
+    }
+
+    #[test]
+    fn clear_and_reuse() {
+        let mut map: BinaryTreeMap<_, _> = (0..50).map(|k| (k, k.to_string())).collect();
+        map.clear();
+        assert!(map.is_empty());
+        assert_eq!(map.get(&10), None);
+        assert_eq!(map.iter().count(), 0);
+
+        assert_eq!(map.insert(10, "ten".to_string()), None);
+        assert_eq!(map.get(&10).map(String::as_str), Some("ten"));
+        assert_eq!(map.len(), 1);
+        assert_eq!(map.height(), 1);
+    }
+
+    #[test]
+    fn split_off() {
+        let mut low: BinaryTreeMap<_, _> = (0..10).map(|k| (k, -k)).collect();
+        let high = low.split_off(&5);
+        assert_eq!((low.len(), high.len()), (5, 5));
+        assert!(low
+            .iter()
+            .map(|(k, v)| (*k, *v))
+            .eq((0..5).map(|k| (k, -k))));
+        assert!(high
+            .iter()
+            .map(|(k, v)| (*k, *v))
+            .eq((5..10).map(|k| (k, -k))));
+
+        let rest = low.split_off(&100);
+        assert!(rest.is_empty());
+        assert_eq!(low.len(), 5);
+        let all = low.split_off(&-1);
+        assert!(low.is_empty());
+        assert_eq!(all.len(), 5);
+    }
+
+    #[test]
+    fn append_overlapping() {
+        let mut first: BinaryTreeMap<_, _> = [(1, "a"), (3, "c"), (5, "e")].into_iter().collect();
+        let mut second: BinaryTreeMap<_, _> = [(2, "B"), (3, "C"), (6, "F")].into_iter().collect();
+        first.append(&mut second);
+
+        assert_eq!(first.len(), 5);
+        assert!(second.is_empty());
+        assert_eq!(first.get(&3), Some(&"C"));
+        assert!(first
+            .into_iter()
+            .eq([(1, "a"), (2, "B"), (3, "C"), (5, "e"), (6, "F")]));
+    }
+
+    #[test]
+    fn into_keys_and_values() {
+        let words = ["pear", "apple", "fig", "kiwi"];
+        let map: BinaryTreeMap<_, _> = words
+            .map(|w| (w.to_string(), w.len()))
+            .into_iter()
+            .collect();
+
+        let keys: Vec<String> = map.clone().into_keys().collect();
+        assert!(keys.is_sorted());
+        assert_eq!(keys, ["apple", "fig", "kiwi", "pear"]);
+        assert_eq!(map.into_values().collect::<Vec<_>>(), [5, 3, 4, 4]);
+    }
+
+    #[test]
+    fn rank_and_select() {
+        let mut map: BinaryTreeMap<_, _> = (0..100).map(|k| (k, k * 2)).collect();
+        assert_eq!(map.rank(&50), 50);
+        assert_eq!(map.rank(&-5), 0);
+        assert_eq!(map.rank(&500), 100);
+        assert_eq!(map.select(10), Some((&10, &20)));
+        assert_eq!(map.select(99), Some((&99, &198)));
+        assert_eq!(map.select(100), None);
+
+        for k in (0..100).filter(|k| k % 3 == 0) {
+            map.remove(&k);
+        }
+        map.entry(1).or_insert(0);
+        map.entry(2).and_modify(|v| *v += 1);
+        assert_eq!(map.pop_first(), Some((1, 2)));
+        assert_eq!(map.root.size(), map.len());
+        // 50 is preceded by 2, 4, 5, 7, 8, ..., 49.
+        assert_eq!(map.rank(&50), 32);
+        assert_eq!(map.select(32), Some((&50, &100)));
+        for (i, (k, _)) in map.iter().enumerate() {
+            assert_eq!(map.rank(k), i);
+            assert_eq!(map.select(i).map(|(k, _)| k), Some(k));
+        }
+    }
+
+    #[test]
+    fn eq_ignores_shape() {
+        let forward: BinaryTreeMap<_, _> = (1..=10).map(|k| (k, k % 3)).collect();
+        let backward: BinaryTreeMap<_, _> = (1..=10).rev().map(|k| (k, k % 3)).collect();
+        assert_ne!(forward.search_path(&1), backward.search_path(&1));
+        assert_eq!(forward, backward);
+
+        let mut other = backward.clone();
+        *other.get_mut(&4).unwrap() = 0;
+        assert_ne!(forward, other);
+        other.remove(&4);
+        assert_ne!(forward, other);
+    }
+
+    #[test]
+    fn index() {
+        let map: BinaryTreeMap<_, _> = [(1, "one".to_string()), (2, "two".to_string())]
+            .into_iter()
+            .collect();
+        assert_eq!(&map[&2], "two");
+        assert_eq!(map[&1].len(), 3);
+    }
+
+    #[test]
+    #[should_panic(expected = "key not found")]
+    fn index_missing_key() {
+        let map: BinaryTreeMap<_, _> = [(1, "one")].into_iter().collect();
+        let _ = map[&3];
+    }
+
+    #[test]
+    fn from_sorted() {
+        let mut map = BinaryTreeMap::from_sorted((0..1023).map(|k| (k, k + 1)).collect());
+        assert_eq!(map.len(), 1023);
+        assert_eq!(map.height(), 10);
+        assert_eq!(map.recount_size(), 1023);
+        assert!(map.into_iter().eq((0..1023).map(|k| (k, k + 1))));
+    }
+
+    #[test]
+    #[should_panic(expected = "strictly increasing")]
+    #[cfg(debug_assertions)]
+    fn from_sorted_unsorted() {
+        BinaryTreeMap::from_sorted(vec![(1, ()), (1, ())]);
+    }
+
+    #[test]
+    #[ignore = "heavy: inserts a million keys"]
+    fn million_ascending_insertions() {
+        let n = 1_000_000;
+        let mut map = BinaryTreeMap::new();
+        for k in 0..n {
+            assert_eq!(map.insert(k, k), None);
+        }
+        assert_eq!(map.len(), n);
+        assert_eq!(map.get(&(n - 1)), Some(&(n - 1)));
+        assert!(map.height() <= 2 * 20);
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn serde_json_round_trip() {
+        let map: BinaryTreeMap<String, Vec<u32>> =
+            [("b", vec![2]), ("a", vec![]), ("c", vec![3, 3])]
+                .into_iter()
+                .map(|(k, v)| (k.to_string(), v))
+                .collect();
+
+        let json = serde_json::to_string(&map).unwrap();
+        assert_eq!(json, r#"{"a":[],"b":[2],"c":[3,3]}"#);
+        let back: BinaryTreeMap<String, Vec<u32>> = serde_json::from_str(&json).unwrap();
+        assert_eq!(back, map);
+    }
+
+    #[test]
+    fn get_key_value_returns_stored_key() {
+        /// Ordered by `id` only.
+        #[derive(Debug)]
+        struct Tagged {
+            id: u32,
+            tag: &'static str,
+        }
+        impl PartialEq for Tagged {
+            fn eq(&self, other: &Self) -> bool {
+                self.id == other.id
+            }
+        }
+        impl Eq for Tagged {}
+        impl PartialOrd for Tagged {
+            fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+                Some(self.cmp(other))
+            }
+        }
+        impl Ord for Tagged {
+            fn cmp(&self, other: &Self) -> Ordering {
+                self.id.cmp(&other.id)
+            }
+        }
+
+        let mut map = BinaryTreeMap::new();
+        map.insert(
+            Tagged {
+                id: 1,
+                tag: "stored",
+            },
+            10,
+        );
+        map.insert(
+            Tagged {
+                id: 2,
+                tag: "other",
+            },
+            20,
+        );
+
+        let query = Tagged {
+            id: 1,
+            tag: "query",
+        };
+        let (key, value) = map.get_key_value(&query).unwrap();
+        assert_eq!(key.tag, "stored");
+        assert_eq!(*value, 10);
+        assert_eq!(map.get_key_value(&Tagged { id: 3, tag: "" }), None);
+    }
+
+    #[test]
+    fn borrowed_lookups() {
+        let mut map: BinaryTreeMap<String, i32> = ["foo", "bar", "baz"]
+            .into_iter()
+            .enumerate()
+            .map(|(i, k)| (k.to_string(), i as i32))
+            .collect();
+
+        assert_eq!(map.get("bar"), Some(&1));
+        assert_eq!(
+            map.get_key_value("baz").map(|(k, _)| k.as_str()),
+            Some("baz")
+        );
+        assert!(map.contains("foo"));
+        assert!(!map.contains("qux"));
+        *map.get_mut("foo").unwrap() += 10;
+        assert_eq!(map.remove("foo"), Some(10));
+        assert_eq!(map.remove("foo"), None);
+        assert_eq!(map.len(), 2);
+    }
+
+    #[test]
+    fn map_values() {
+        let map: BinaryTreeMap<_, _> = [3, 1, 4, 5, 9, 2, 6]
+            .map(|k| (k, k * 11))
+            .into_iter()
+            .collect();
+        let mut seen = Vec::new();
+        let strings = map.map_values(|v| {
+            seen.push(v);
+            v.to_string()
+        });
+
+        assert_eq!(seen, [11, 22, 33, 44, 55, 66, 99]);
+        assert_eq!(strings.len(), 7);
+        assert_eq!(strings.get(&4).map(String::as_str), Some("44"));
+        assert!(strings.keys().copied().eq([1, 2, 3, 4, 5, 6, 9]));
+    }
+
+    #[test]
+    fn iterate_in_descending_order() {
+        let map: BinaryTreeMap<_, _> = (0..50).map(|k| ((k * 7) % 50, k)).collect();
+        let forward: Vec<_> = map.iter().collect();
+        let mut backward: Vec<_> = map.iter().rev().collect();
+        backward.reverse();
+        assert_eq!(backward, forward);
+
+        // Both ends meet without yielding an entry twice.
+        let mut iter = map.iter();
+        let mut keys = Vec::new();
+        while let (Some((front, _)), Some((back, _))) = (iter.next(), iter.next_back()) {
+            keys.push(*front);
+            keys.push(*back);
+        }
+        keys.sort();
+        assert!(keys.into_iter().eq(0..50));
+
+        let owned: Vec<_> = map.clone().into_iter().rev().map(|(k, _)| k).collect();
+        assert!(owned.into_iter().eq((0..50).rev()));
+        let mut iter = map.into_iter();
+        assert_eq!(iter.next().map(|(k, _)| k), Some(0));
+        assert_eq!(iter.next_back().map(|(k, _)| k), Some(49));
+        assert_eq!(iter.count(), 48);
+    }
+
+    #[test]
+    fn iterators_know_their_length() {
+        let mut map: BinaryTreeMap<_, _> = (0..10).map(|k| (k, k)).collect();
+
+        let mut iter = map.iter();
+        assert_eq!(iter.len(), 10);
+        iter.next();
+        iter.next_back();
+        assert_eq!(iter.size_hint(), (8, Some(8)));
+
+        let mut iter_mut = map.iter_mut();
+        assert_eq!(iter_mut.len(), 10);
+        iter_mut.next();
+        iter_mut.next();
+        assert_eq!(iter_mut.len(), 8);
+
+        let mut into_iter = map.into_iter();
+        assert_eq!(into_iter.len(), 10);
+        into_iter.next();
+        into_iter.next_back();
+        into_iter.next_back();
+        assert_eq!(into_iter.size_hint(), (7, Some(7)));
+        assert_eq!(into_iter.by_ref().count(), 7);
+        assert_eq!(into_iter.len(), 0);
+    }
+
+    #[test]
+    fn assert_valid_accepts_correct_map() {
+        let mut map: BinaryTreeMap<_, _> = (0..200).map(|k| ((k * 37) % 200, k)).collect();
+        map.assert_valid();
+        map.retain(|k, _| k % 3 != 0);
+        map.assert_valid();
+        BinaryTreeMap::<u8, ()>::new().assert_valid();
+    }
+
+    #[test]
+    #[should_panic(expected = "but the map has length")]
+    fn assert_valid_catches_wrong_size() {
+        let mut map: BinaryTreeMap<_, _> = (0..10).map(|k| (k, k)).collect();
+        map.size += 1;
+        map.assert_valid();
+    }
+
+    #[test]
+    #[should_panic(expected = "out of order")]
+    fn assert_valid_catches_unsorted_keys() {
+        let mut map: BinaryTreeMap<_, _> = (0..10).map(|k| (k, k)).collect();
+        if let BinaryTreeNode::Node(root) = &mut map.root {
+            root.key = 100;
+        }
+        map.assert_valid();
+    }
+
+    #[test]
+    fn btree_map_round_trip() {
+        let map: BinaryTreeMap<_, _> = (0..100).map(|k| ((k * 13) % 100, k)).collect();
+        let btree = BTreeMap::from(map.clone());
+        assert_eq!(btree.len(), 100);
+        assert!(btree.iter().eq(map.iter()));
+
+        let back = BinaryTreeMap::from(btree);
+        back.assert_valid();
+        assert_eq!(back.len(), 100);
+        assert_eq!(back, map);
+    }
+
+    #[test]
+    fn get_or_insert_with_calls_once() {
+        let mut map = BinaryTreeMap::new();
+        let mut calls = 0;
+        for _ in 0..2 {
+            let value = map.get_or_insert_with("fib(30)", || {
+                calls += 1;
+                832040
+            });
+            *value += 1;
+        }
+        assert_eq!(calls, 1);
+        assert_eq!(map.len(), 1);
+        assert_eq!(map.get("fib(30)"), Some(&832042));
+    }
+
+    #[test]
+    fn count_range() {
+        let map: BinaryTreeMap<_, _> = (0..100).map(|k| (k, ())).collect();
+        assert_eq!(map.count_range(25..75), 50);
+        assert_eq!(map.count_range(25..=75), 51);
+        assert_eq!(
+            map.count_range((Bound::Excluded(25), Bound::Excluded(75))),
+            49
+        );
+        assert_eq!(map.count_range(..10), 10);
+        assert_eq!(map.count_range(90..), 10);
+        assert_eq!(map.count_range(..), 100);
+        assert_eq!(map.count_range(30..30), 0);
+        assert_eq!(map.count_range(200..300), 0);
+        #[allow(clippy::reversed_empty_ranges)]
+        let reversed = 75..25;
+        assert_eq!(map.count_range(reversed), 0);
+        for (start, end) in [(-5, 3), (17, 64), (99, 120)] {
+            assert_eq!(map.count_range(start..end), map.range(start..end).count());
+        }
+    }
 }